@@ -1,6 +1,12 @@
-use std::{fs::File, path::PathBuf};
+use std::path::PathBuf;
 
-use clap:: {Args, Parser, Subcommand};
+use clap:: {Args, Parser, Subcommand, ValueEnum};
+
+use pngme::lsb::Channels;
+use pngme::ops::{Method, DEFAULT_CHUNK_SIZE};
+use pngme::png::ChunkPosition;
+use pngme::raster::DEFAULT_IDAT_SIZE;
+use pngme::strip::Preset;
 
 
 #[derive(Parser, Debug)]
@@ -19,38 +25,334 @@ pub enum Commands {
     Remove(RemoveArgs),
     /// Print a list of PNG chunks that can be searched for messages
     Print(PrintArgs),
+    /// Read and write tEXt, zTXt and iTXt metadata
+    #[command(subcommand)]
+    Text(TextCommands),
+    /// Check chunk ordering and other structural rules of the PNG spec
+    Validate(ValidateArgs),
+    /// Salvage the readable chunks of a damaged PNG
+    Repair(RepairArgs),
+    /// Rewrite chunks with correct CRCs, e.g. after editing them by hand
+    FixCrc(FixCrcArgs),
+    /// Report how much data each hiding method can store in an image
+    Capacity(CapacityArgs),
+    /// Split the image data into IDAT chunks of a chosen size
+    Rechunk(RechunkArgs),
+    /// Remove identifying and other ancillary chunks before publishing
+    Strip(StripArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TextCommands {
+    /// Print the text stored under a keyword
+    Get(TextGetArgs),
+    /// Store text under a keyword, replacing any existing text for it
+    Set(TextSetArgs),
+    /// List every text chunk
+    List(TextListArgs),
+    /// Remove every text chunk with a keyword
+    Remove(TextRemoveArgs),
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct EncodeArgs {
-    /// PNG file path
+    /// PNG file path, - for stdin
     pub file_path: PathBuf,
-    /// Chunk Type
-    pub chunk_type: String,
+    /// Chunk Type, required with --method chunk and left out with --method lsb
+    #[arg(required_unless_present = "method")]
+    pub chunk_type: Option<String>,
     /// Secret message
-    pub message: String,
-    /// Optional file output path
-    pub output_file: Option<PathBuf>
+    #[arg(short, long, required_unless_present = "file", conflicts_with = "file")]
+    pub message: Option<String>,
+    /// Output file path, - for stdout. Defaults to the input file, or to
+    /// stdout when the image comes from stdin
+    #[arg(short, long = "out")]
+    pub output_file: Option<PathBuf>,
+    /// Embed the raw bytes of this file instead of a message
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+    /// Deflate the message or file before storing it
+    #[arg(long)]
+    pub compress: bool,
+    /// Store the message in a chunk or in the pixels themselves
+    #[arg(long, value_enum, default_value_t = MethodArg::Chunk)]
+    pub method: MethodArg,
+    /// Channels whose least significant bits carry the message with
+    /// --method lsb, any of r, g, b and a
+    #[arg(long, default_value = "rgb")]
+    pub channels: Channels,
+    /// Low bits of each sample used with --method lsb: 1, 2 or 4
+    #[arg(long, default_value_t = 1)]
+    pub bits: u8,
+    /// Where to place the message chunk
+    #[arg(long, value_enum, default_value_t = PositionArg::BeforeIend)]
+    pub position: PositionArg,
+    /// Encrypt the message with a key derived from this passphrase
+    #[arg(long, env = "PNGME_PASSPHRASE")]
+    pub passphrase: Option<String>,
+    /// Largest chunk to write; longer messages are split across chunks
+    #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+    pub chunk_size: usize,
+    /// With --method lsb, keep unknown chunks that are not safe to copy
+    /// instead of dropping them as the PNG spec asks
+    #[arg(long)]
+    pub keep_unsafe_chunks: bool,
+    /// Keep the previous contents of the output file as <name>.bak
+    #[arg(long)]
+    pub backup: bool,
+}
+
+/// `ops::Method` as a command line value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MethodArg {
+    /// A chunk of its own, visible to anything that lists chunks
+    Chunk,
+    /// The least significant bits of the pixels
+    Lsb,
+}
+
+impl From<MethodArg> for Method {
+    fn from(method: MethodArg) -> Method {
+        match method {
+            MethodArg::Chunk => Method::Chunk,
+            MethodArg::Lsb => Method::Lsb,
+        }
+    }
+}
+
+/// `png::ChunkPosition` as a command line value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PositionArg {
+    /// Directly after IHDR
+    AfterIhdr,
+    /// In front of the first IDAT
+    BeforeFirstIdat,
+    /// Directly after the last IDAT
+    AfterLastIdat,
+    /// In front of IEND, so the file still ends with IEND
+    BeforeIend,
+}
+
+impl From<PositionArg> for ChunkPosition {
+    fn from(position: PositionArg) -> ChunkPosition {
+        match position {
+            PositionArg::AfterIhdr => ChunkPosition::AfterIhdr,
+            PositionArg::BeforeFirstIdat => ChunkPosition::BeforeFirstIdat,
+            PositionArg::AfterLastIdat => ChunkPosition::AfterLastIdat,
+            PositionArg::BeforeIend => ChunkPosition::BeforeIend,
+        }
+    }
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct DecodeArgs {
-     /// PNG file path
+     /// PNG file path, - for stdin
      pub file_path: PathBuf,
-     /// Chunk Type
-     pub chunk_type: String,
+     /// Chunk Type, left out with --method lsb
+     #[arg(required_unless_present = "method")]
+     pub chunk_type: Option<String>,
+     /// Where the message was stored
+     #[arg(long, value_enum, default_value_t = MethodArg::Chunk)]
+     pub method: MethodArg,
+     /// Channels the message was hidden in with --method lsb
+     #[arg(long, default_value = "rgb")]
+     pub channels: Channels,
+     /// Low bits of each sample the message was hidden in with --method lsb
+     #[arg(long, default_value_t = 1)]
+     pub bits: u8,
+     /// Passphrase the message was encrypted with
+     #[arg(long, env = "PNGME_PASSPHRASE")]
+     pub passphrase: Option<String>,
+     /// Write the raw message bytes to this file instead of printing them, - for stdout
+     #[arg(long)]
+     pub out: Option<PathBuf>,
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct RemoveArgs {
-     /// Input PNG file path
+     /// Input PNG file path, - to read stdin and write the result to stdout
      pub file_path: PathBuf,
      /// Chunk Type
      pub chunk_type: String,
+     /// Remove every chunk of this type instead of only the first
+     #[arg(long, conflicts_with = "index")]
+     pub all: bool,
+     /// Remove only the Nth chunk of this type, counting from 0
+     #[arg(long)]
+     pub index: Option<usize>,
+     /// Keep the previous contents of the file as <name>.bak
+     #[arg(long)]
+     pub backup: bool,
 }
 
 #[derive(Debug, Args, Clone)]
 pub struct PrintArgs {
+    /// Input PNG file path, - for stdin
+    pub file_path: PathBuf,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = PrintFormat::Text)]
+    pub format: PrintFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PrintFormat {
+    /// One human readable line per chunk
+    Text,
+    /// A JSON array with one object per chunk
+    Json,
+    /// A header row, then one row per chunk
+    Csv,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct ValidateArgs {
     /// Input PNG file path
     pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct RepairArgs {
+    /// Damaged PNG file path
+    pub file_path: PathBuf,
+    /// Where to write the repaired image instead of replacing the input
+    pub output_file: Option<PathBuf>,
+    /// Skip unreadable bytes and keep looking for intact chunks instead of
+    /// stopping at the first one
+    #[arg(long)]
+    pub resync: bool,
+    /// Keep the previous contents of the output file as <name>.bak
+    #[arg(long)]
+    pub backup: bool,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct FixCrcArgs {
+    /// PNG file path
+    pub file_path: PathBuf,
+    /// Where to write the fixed image instead of replacing the input
+    pub output_file: Option<PathBuf>,
+    /// Only fix chunks of this type; may be repeated
+    #[arg(long)]
+    pub chunk_type: Vec<String>,
+    /// Only fix the chunk at this position in the file, counting from 0;
+    /// may be repeated
+    #[arg(long)]
+    pub index: Vec<usize>,
+    /// Keep the previous contents of the output file as <name>.bak
+    #[arg(long)]
+    pub backup: bool,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct CapacityArgs {
+    /// Input PNG file path
+    pub file_path: PathBuf,
+    /// Channels to count for LSB embedding, any of r, g, b and a
+    #[arg(long, default_value = "rgb")]
+    pub channels: Channels,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct RechunkArgs {
+    /// PNG file path, - for stdin
+    pub file_path: PathBuf,
+    /// Where to write the image instead of replacing the input, - for stdout
+    pub output_file: Option<PathBuf>,
+    /// Largest IDAT chunk to write
+    #[arg(long, default_value_t = DEFAULT_IDAT_SIZE, conflicts_with = "single")]
+    pub size: usize,
+    /// Put all the image data in one IDAT chunk
+    #[arg(long)]
+    pub single: bool,
+    /// Keep the previous contents of the output file as <name>.bak
+    #[arg(long)]
+    pub backup: bool,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct StripArgs {
+    /// PNG file path, - for stdin
+    pub file_path: PathBuf,
+    /// Where to write the image instead of replacing the input, - for stdout
+    pub output_file: Option<PathBuf>,
+    /// Chunks removed before --keep and --drop apply
+    #[arg(long, value_enum, default_value_t = PresetArg::Safe)]
+    pub preset: PresetArg,
+    /// Keep chunks of this type whatever else is removed; may be repeated
+    #[arg(long)]
+    pub keep: Vec<String>,
+    /// Also remove chunks of this ancillary type; may be repeated
+    #[arg(long)]
+    pub drop: Vec<String>,
+    /// Keep the previous contents of the output file as <name>.bak
+    #[arg(long)]
+    pub backup: bool,
+}
+
+/// `strip::Preset` as a command line value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PresetArg {
+    /// eXIf, tEXt, iTXt, zTXt, tIME and private chunks
+    Safe,
+    /// Every ancillary chunk except gAMA, sRGB, iCCP and cHRM
+    AllAncillary,
+}
+
+impl From<PresetArg> for Preset {
+    fn from(preset: PresetArg) -> Preset {
+        match preset {
+            PresetArg::Safe => Preset::Safe,
+            PresetArg::AllAncillary => Preset::AllAncillary,
+        }
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct TextGetArgs {
+    /// Input PNG file path
+    pub file_path: PathBuf,
+    /// Keyword, e.g. Title or Author
+    pub keyword: String,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct TextSetArgs {
+    /// PNG file path
+    pub file_path: PathBuf,
+    /// Keyword, e.g. Title or Author
+    pub keyword: String,
+    /// Text to store
+    pub text: String,
+    /// Compress the text (zTXt, or compressed iTXt)
+    #[arg(long)]
+    pub compressed: bool,
+    /// Store as UTF-8 iTXt instead of Latin-1 tEXt/zTXt
+    #[arg(long)]
+    pub international: bool,
+    /// Language tag for iTXt, e.g. en-GB (implies --international)
+    #[arg(long)]
+    pub language: Option<String>,
+    /// Keyword translated into the language (implies --international)
+    #[arg(long)]
+    pub translated_keyword: Option<String>,
+    /// Keep the previous contents of the file as <name>.bak
+    #[arg(long)]
+    pub backup: bool,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct TextListArgs {
+    /// Input PNG file path
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct TextRemoveArgs {
+    /// PNG file path
+    pub file_path: PathBuf,
+    /// Keyword, e.g. Title or Author
+    pub keyword: String,
+    /// Keep the previous contents of the file as <name>.bak
+    #[arg(long)]
+    pub backup: bool,
 }
\ No newline at end of file
@@ -1,9 +1,9 @@
-use crate::{chunk_type::ChunkType, Error};
+use crate::{chunk_type::{ChunkType, ChunkTypeError}, png::PngError, Error};
 use std::str::FromStr;
 use std::fmt;
 use crc::{Crc, CRC_32_ISO_HDLC};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chunk {
     length: u32,
     pub chunk_type: ChunkType,
@@ -23,23 +23,52 @@ impl fmt::Display for Chunk {
     }
 }
 
-impl TryFrom<&Vec<u8>> for Chunk{
-    type Error = &'static str;
+impl TryFrom<&Vec<u8>> for Chunk {
+    type Error = PngError;
 
     fn try_from(bytes: &Vec<u8>) -> Result<Self, Self::Error> {
+        Chunk::parse(bytes, true)
+    }
+}
+
+
+impl Chunk {
+    /// The spec caps chunk data at 2^31 - 1 bytes.
+    pub const MAX_LENGTH: usize = i32::MAX as usize;
+
+    /// Parses one chunk. With `check_crc` false a wrong CRC is kept as it
+    /// was stored instead of being an error, see `recompute_crc`.
+    pub fn parse(bytes: &[u8], check_crc: bool) -> Result<Chunk, PngError> {
         if bytes.len() < 12 {
-            return Err("Not enough bytes for a valid Chunk")
+            return Err(PngError::TruncatedLength { offset: 0, chunk_index: 0 })
         }
 
         let length_bytes: [u8; 4] = [bytes[0], bytes[1], bytes[2], bytes[3]];
         let length = u32::from_be_bytes(length_bytes);
-        // println!("length: {}", length);
+
         let chunk_type_bytes: [u8; 4] = [bytes[4], bytes[5], bytes[6], bytes[7]];
-        let chunk_type = ChunkType::try_from(chunk_type_bytes).unwrap();
-        // println!("chunk type: {}", chunk_type);
+        let chunk_type = ChunkType::try_from(chunk_type_bytes).map_err(|e| {
+            let position = match e {
+                ChunkTypeError::NotAlphabetic { position, .. } => position,
+                // the reserved bit is bit 5 of the third byte
+                ChunkTypeError::ReservedBitSet { .. } => 2,
+            };
+            PngError::InvalidChunkType { offset: 4 + position, chunk_index: 0, bytes: chunk_type_bytes }
+        })?;
+
+        // the declared length has to match what is actually between the type and the CRC
+        let available = bytes.len() - 12;
+        if length as usize != available {
+            return Err(PngError::TruncatedData {
+                offset: 8,
+                chunk_index: 0,
+                chunk_type: chunk_type_bytes,
+                expected: length as usize,
+                available,
+            })
+        }
 
         let chunk_data = bytes[8..bytes.len() - 4].to_vec();
-        // println!("chunk data arr: {:?}", chunk_data);
 
         let crc_bytes: [u8; 4] = [
             bytes[bytes.len() - 4],
@@ -47,36 +76,48 @@ impl TryFrom<&Vec<u8>> for Chunk{
             bytes[bytes.len() - 2],
             bytes[bytes.len() - 1],
         ];
-        // println!("crc arr: {:?}", crc_bytes);
         let crc = u32::from_be_bytes(crc_bytes);
-        // println!("crc {}", crc);
 
         let to_check = [&chunk_type.bytes(), chunk_data.as_slice()].concat();
-        // println!("expected crc {}", Chunk::calc_checksum(&to_check));
-        // println!("compare {} to {}",Chunk::calc_checksum(&to_check), crc);
-        if Chunk::calc_checksum(&to_check) != crc {
-            return Err("Data is corrupted")
+        let actual = Chunk::calc_checksum(&to_check);
+        if check_crc && actual != crc {
+            return Err(PngError::CrcMismatch {
+                offset: bytes.len() - 4,
+                chunk_index: 0,
+                chunk_type: chunk_type_bytes,
+                expected: crc,
+                actual,
+            })
         }
 
         Ok(Chunk { length, chunk_type, chunk_data, crc })
     }
-}
 
-
-impl Chunk {
+    /// Builds a chunk, panicking if `data` is longer than `MAX_LENGTH`. Use
+    /// `try_new` for data whose size is not known to be small.
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
+        Chunk::try_new(chunk_type, data).expect("chunk data exceeds Chunk::MAX_LENGTH")
+    }
+
+    pub fn try_new(chunk_type: ChunkType, data: Vec<u8>) -> Result<Chunk, Error> {
+        if data.len() > Chunk::MAX_LENGTH {
+            return Err(format!(
+                "chunk data is {} bytes, at most {} fit in a chunk",
+                data.len(),
+                Chunk::MAX_LENGTH
+            )
+            .into())
+        }
+
         let to_check = [&chunk_type.bytes(), data.as_slice()].concat();
         let crc = Chunk::calc_checksum(&to_check);
 
-        
-        let chunk = Chunk {
-            length: data.len().try_into().unwrap(),
+        Ok(Chunk {
+            length: data.len() as u32,
             chunk_type,
             chunk_data: data,
             crc,
-        };
-
-        chunk
+        })
     }
 
     pub fn length(&self) -> u32 {
@@ -95,11 +136,19 @@ impl Chunk {
         self.crc
     }
 
+    /// Replaces the stored CRC with the one computed over type and data and
+    /// returns whether it was wrong.
+    pub fn recompute_crc(&mut self) -> bool {
+        let to_check = [&self.chunk_type.bytes(), self.chunk_data.as_slice()].concat();
+        let previous = std::mem::replace(&mut self.crc, Chunk::calc_checksum(&to_check));
+        previous != self.crc
+    }
+
+    /// The chunk data as text, or an error if it is not valid UTF-8.
     pub fn data_as_string(&self) -> Result<String, Error> {
-        let chunk_message:String = String::from_utf8(self.chunk_data.to_vec()).expect("Invalid UTF-8");
+        let chunk_message: String = String::from_utf8(self.chunk_data.to_vec())?;
 
         Ok(chunk_message)
-        
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -123,10 +172,8 @@ impl Chunk {
 }
 
 pub fn chunk_from_strings(chunk_type_input: &str, message: &str) -> Result<Chunk, Error> {
-    let chunk_type = ChunkType::from_str(chunk_type_input).unwrap();
-     let chunk = Chunk::new(chunk_type, message.as_bytes().to_vec());
-
-     Ok(chunk)
+    let chunk_type = ChunkType::from_str(chunk_type_input)?;
+    Chunk::try_new(chunk_type, message.as_bytes().to_vec())
 }
 
 
@@ -184,6 +231,12 @@ mod tests {
         assert_eq!(chunk_string, expected_chunk_string);
     }
 
+    #[test]
+    fn test_chunk_string_invalid_utf8() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![0xff, 0xfe]);
+        assert!(chunk.data_as_string().is_err());
+    }
+
     #[test]
     fn test_chunk_crc() {
         let chunk = testing_chunk();
@@ -238,6 +291,19 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_parse_ignoring_crc_and_recompute() {
+        let mut bytes = testing_chunk().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+
+        let mut chunk = Chunk::parse(&bytes, false).unwrap();
+        assert_eq!(chunk.crc(), 2882656334 ^ 1);
+        assert!(chunk.recompute_crc());
+        assert_eq!(chunk.crc(), 2882656334);
+        assert!(!chunk.recompute_crc());
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -2,10 +2,16 @@ use crate::Error;
 use std::fmt;
 use std::io;
 use std::str::FromStr;
-use regex::Regex;
 
+/// Ancillary chunk types of the PNG spec and of APNG. pngme leaves their
+/// contents valid whatever it changes.
+const KNOWN_ANCILLARY: [&[u8; 4]; 21] = [
+    b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCV", b"cLLI", b"bKGD", b"hIST", b"tRNS",
+    b"eXIf", b"pHYs", b"sPLT", b"tIME", b"iTXt", b"tEXt", b"zTXt", b"acTL", b"fcTL", b"fdAT",
+];
 
-#[derive(Debug, PartialEq)]
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ChunkType {
     ancillary: u8,
     private: u8,
@@ -13,21 +19,56 @@ pub struct ChunkType {
     safe_to_copy: u8
 }
 
+/// Why a set of bytes is not a valid chunk type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkTypeError {
+    /// The byte at `position` is not an ASCII letter A-Z or a-z
+    NotAlphabetic { position: usize, byte: u8 },
+    /// Bit 5 of the third byte is set, i.e. the third letter is lowercase
+    ReservedBitSet { byte: u8 },
+}
+
+/// The property each of the four bytes of a chunk type carries in bit 5.
+const PROPERTY_NAMES: [&str; 4] = ["ancillary", "private", "reserved", "safe-to-copy"];
+
+impl fmt::Display for ChunkTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChunkTypeError::NotAlphabetic { position, byte } => write!(
+                f,
+                "byte {} ({} property) is {:#04x}, must be a ASCII character A-Z or a-z",
+                position, PROPERTY_NAMES[*position], byte
+            ),
+            ChunkTypeError::ReservedBitSet { byte } => write!(
+                f,
+                "reserved bit is set: third byte is '{}' but must be uppercase",
+                char::from(*byte)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChunkTypeError {}
+
 impl TryFrom<[u8; 4]> for ChunkType {
-    type Error = ();
+    type Error = ChunkTypeError;
 
     fn try_from(byte_array: [u8; 4]) -> Result<Self, Self::Error> {
+        if let Some(position) = byte_array.iter().position(|byte| !byte.is_ascii_alphabetic()) {
+            return Err(ChunkTypeError::NotAlphabetic { position, byte: byte_array[position] })
+        }
 
-         Ok(ChunkType { ancillary: byte_array[0], private: byte_array[1], reserved: byte_array[2], safe_to_copy: byte_array[3] })
+        Ok(ChunkType { ancillary: byte_array[0], private: byte_array[1], reserved: byte_array[2], safe_to_copy: byte_array[3] })
     }
 }
 
 impl fmt::Display for ChunkType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-         let bytes: [u8; 4] = [self.ancillary, self.private, self.reserved, self.safe_to_copy];
-        let ascii_string = String::from_utf8(bytes.to_vec()).expect("Invalid UTF-8");
-
-        write!(f,"{}", ascii_string)
+        // construction guarantees ASCII letters, so every byte is a char
+        for byte in self.bytes() {
+            write!(f, "{}", char::from(byte))?;
+        }
+        Ok(())
     }
 
 }
@@ -35,33 +76,12 @@ impl fmt::Display for ChunkType {
 impl FromStr for ChunkType {
     type Err = Error;
    
-        fn from_str(input: &str) -> Result<ChunkType, Error> {
-
-        if input.len() != 4 {
-            return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "Input string slice must have 4 bytes",)))
-        }
-
-        let letters = Regex::new(r"^[a-zA-Z]+$").unwrap();
-        
-        if !letters.is_match(input) {
-            return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "Must be a ASCII character A-Z or a-z",)))
-        }
-        
-        
-
-        let ancillary = input.as_bytes()[0];
-        let private = input.as_bytes()[1];
-        let reserved = input.as_bytes()[2];
-        let safe_to_copy = input.as_bytes()[3];
+    fn from_str(input: &str) -> Result<ChunkType, Error> {
+        let bytes: [u8; 4] = input.as_bytes().try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Input string slice must have 4 bytes")
+        })?;
 
-        let result = ChunkType {
-            ancillary,
-            private,
-            reserved,
-            safe_to_copy,
-        };
-
-        Ok(result)
+        Ok(ChunkType::try_from(bytes)?)
     }
     
 }
@@ -71,49 +91,58 @@ impl ChunkType {
         [self.ancillary, self.private, self.reserved, self.safe_to_copy]
     }
 
+    /// A chunk type is valid when all four bytes are ASCII letters and the
+    /// reserved bit is clear.
     pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Like `is_valid`, but says which rule the chunk type breaks.
+    pub fn validate(&self) -> Result<(), ChunkTypeError> {
+        if let Some(position) = self.bytes().iter().position(|byte| !byte.is_ascii_alphabetic()) {
+            return Err(ChunkTypeError::NotAlphabetic { position, byte: self.bytes()[position] })
+        }
+
+        if !self.is_reserved_bit_valid() {
+            return Err(ChunkTypeError::ReservedBitSet { byte: self.reserved })
+        }
 
-         if self.ancillary.is_ascii() &&
-         self.private.is_ascii() &&
-         self.reserved.is_ascii() &&
-         self.safe_to_copy.is_ascii(){
-            return true
-         } else {
-            return false
-         }
+        Ok(())
     }
 
     pub fn is_critical(&self) -> bool {
         let byte = self.ancillary;
 
         // 0 represents critial
-        return !check_fifth_bit(&byte);
-        
+        !check_fifth_bit(&byte)
     }
 
     pub fn is_public(&self) -> bool {
         let byte = self.private;
 
         // 0 represents public
-        return !check_fifth_bit(&byte);
-        
+        !check_fifth_bit(&byte)
     }
 
     pub fn is_reserved_bit_valid(&self) -> bool {
         let byte = self.reserved;
 
         // 0 represents reserved
-        return !check_fifth_bit(&byte);
-        
+        !check_fifth_bit(&byte)
     }
 
     pub fn is_safe_to_copy(&self) -> bool {
         let byte = self.safe_to_copy;
-        let fifth_bit = (byte & (1 << 5)) >> 5;
 
         // 1 represents is safe
-        return check_fifth_bit(&byte);
-        
+        check_fifth_bit(&byte)
+    }
+
+    /// Whether the chunk type is one of the four critical chunks or a
+    /// standard ancillary chunk.
+    pub fn is_known(&self) -> bool {
+        let bytes = self.bytes();
+        matches!(&bytes, b"IHDR" | b"PLTE" | b"IDAT" | b"IEND") || KNOWN_ANCILLARY.contains(&&bytes)
     }
 
 }
@@ -122,8 +151,7 @@ impl ChunkType {
 fn check_fifth_bit(byte :&u8) -> bool {
     let fifth_bit = (byte & (1 << 5)) >> 5;
 
-    if fifth_bit == 1 {return true }
-    else {return false;}
+    fifth_bit == 1
 }
 
 
@@ -206,12 +234,60 @@ mod tests {
     #[test]
     pub fn test_invalid_chunk_is_valid() {
         let chunk = ChunkType::from_str("Rust").unwrap();
-        assert!(chunk.is_valid());
+        assert!(!chunk.is_valid());
 
         let chunk = ChunkType::from_str("Ru1t");
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_validate_reserved_bit() {
+        let chunk = ChunkType::from_str("Rust").unwrap();
+        assert_eq!(chunk.validate(), Err(ChunkTypeError::ReservedBitSet { byte: b's' }));
+
+        let chunk = ChunkType::from_str("RuSt").unwrap();
+        assert_eq!(chunk.validate(), Ok(()));
+    }
+
+    #[test]
+    pub fn test_chunk_type_rejects_non_letters() {
+        for position in 0..4 {
+            let mut bytes = *b"RuSt";
+            bytes[position] = b'1';
+            assert_eq!(
+                ChunkType::try_from(bytes),
+                Err(ChunkTypeError::NotAlphabetic { position, byte: b'1' })
+            );
+        }
+
+        assert!(ChunkType::try_from([0xC3, 0xA9, 83, 116]).is_err());
+        assert!(ChunkType::try_from([82, 117, 83, 0]).is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_type_from_str_invalid() {
+        assert!(ChunkType::from_str("RuS").is_err());
+        assert!(ChunkType::from_str("RuStt").is_err());
+        assert!(ChunkType::from_str("Ru\u{e9}").is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_type_error_names_property() {
+        let error = ChunkType::try_from(*b"Ru1t").unwrap_err();
+        assert!(error.to_string().contains("reserved"));
+
+        let error = ChunkType::from_str("Rust").unwrap().validate().unwrap_err();
+        assert!(error.to_string().contains("reserved bit"));
+    }
+
+    #[test]
+    pub fn test_chunk_type_is_known() {
+        assert!(ChunkType::from_str("IDAT").unwrap().is_known());
+        assert!(ChunkType::from_str("tRNS").unwrap().is_known());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_known());
+        assert!(!ChunkType::from_str("trns").unwrap().is_known());
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
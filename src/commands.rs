@@ -1,106 +1,695 @@
-use crate::Error;
-use crate::args::{EncodeArgs, DecodeArgs, RemoveArgs, PrintArgs};
-use crate::chunk::Chunk;
-use crate::chunk_type::ChunkType;
-use crate::png::Png;
-use std::fs;
-use std::path::PathBuf;
+use crate::args::{CapacityArgs, EncodeArgs, DecodeArgs, MethodArg, RemoveArgs, FixCrcArgs, PrintArgs, PrintFormat, RechunkArgs, RepairArgs, StripArgs, TextCommands, TextSetArgs, ValidateArgs};
+use pngme::Error;
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::crypto;
+use pngme::ihdr::Ihdr;
+use pngme::lsb;
+use pngme::ops::{self, DecodeOptions, EncodeOptions, Selection};
+use pngme::payload;
+use pngme::png::{ChunkPosition, Png};
+use pngme::stream::{self, PngReader, PngWriter};
+use pngme::strip::StripPolicy;
+use pngme::text_chunk::{TextChunk, TextKind};
+use pngme::validate::Severity;
+use std::fs::{self, File, OpenOptions};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::io;
+use serde_json::{json, Value};
 
 
 pub fn encode(args: &EncodeArgs) -> Result<(), Error> {
-    // println!("{:?}", args);
-    let png_bytes = fs::read(&args.file_path)?;
-    // println!("png bytes {:?}", png_bytes);
-    let mut png = Png::try_from(png_bytes.as_slice()).unwrap();
-    let chunk_type = ChunkType::from_str(&args.chunk_type).unwrap();
-    let message_as_bytes = args.message.as_bytes().to_vec();
-    let chunk = Chunk::new(chunk_type, message_as_bytes);
-    
-    png.append_chunk(chunk);
+    if let (MethodArg::Lsb, Some(chunk_type)) = (args.method, &args.chunk_type) {
+        return Err(format!("unexpected argument {:?}, --method lsb takes no chunk type", chunk_type).into())
+    }
+    let options = EncodeOptions {
+        method: args.method.into(),
+        chunk_type: args.chunk_type.clone(),
+        position: args.position.into(),
+        chunk_size: args.chunk_size,
+        channels: args.channels,
+        bits: args.bits,
+        compress: args.compress,
+        file_name: args.file.as_ref().and_then(|path| path.file_name()).map(|name| name.to_string_lossy().into_owned()),
+        passphrase: args.passphrase.clone(),
+        keep_unsafe_chunks: args.keep_unsafe_chunks,
+    };
+    let message = match &args.file {
+        Some(path) => read_input(path)?,
+        None => args.message.clone().ok_or("a message or --file is required")?.into_bytes(),
+    };
 
-    if let Some(output_file) = &args.output_file {
-        fs::write(output_file, png.as_bytes());
-    } else {
-        fs::write(&args.file_path, png.as_bytes());
+    let data = ops::pack(&message, &options)?;
+    if args.compress {
+        let compressed = deflated_length(data.len(), &options);
+        eprintln!(
+            "compressed {} bytes to {} ({:.1}%)",
+            message.len(),
+            compressed,
+            compressed as f64 * 100.0 / message.len().max(1) as f64
+        );
     }
-    println!("{}", png);
+    let output_file = args.output_file.as_ref().unwrap_or(&args.file_path);
+
+    if args.method == MethodArg::Lsb {
+        let mut png = create_png_struct(&args.file_path)?;
+        let capacity = lsb::capacity(&png, args.channels, args.bits)?;
+        lsb::embed(&mut png, &data, args.channels, args.bits)?;
+        if !args.keep_unsafe_chunks {
+            warn_dropped(&png.drop_unsafe_to_copy());
+        }
+        write_png_with_backup(output_file, args.backup, &png)?;
+
+        eprintln!(
+            "hid {} bytes in the pixels of {} ({} of {} bytes of capacity used)",
+            data.len(),
+            output_file.display(),
+            data.len(),
+            capacity
+        );
+        return Ok(())
+    }
+
+    let reader = open_png(&args.file_path)?;
+    let chunk_type = options.chunk_type.as_deref().ok_or("a chunk type is required")?;
+    let chunks = ops::message_chunks(&data, chunk_type, args.chunk_size)?;
+    let count = chunks.len();
+
+    rewrite(output_file, args.backup, |writer| {
+        stream::copy_with_inserted(reader, writer, chunks, args.position.into())
+    })?;
+    eprintln!("wrote {} chunk(s) of type {} to {}", count, chunk_type, output_file.display());
     
     Ok(())
 }
 
+/// The bytes of the deflated message in `stored` bytes packed with
+/// `options`, without the payload header and encryption around them.
+fn deflated_length(stored: usize, options: &EncodeOptions) -> usize {
+    let mut overhead = payload::HEADER_LEN + options.file_name.as_ref().map_or(0, String::len);
+    if options.passphrase.is_some() {
+        overhead += crypto::HEADER_LEN + crypto::TAG_LEN;
+    }
+    stored - overhead
+}
+
+/// Lists the chunks `Png::drop_unsafe_to_copy` removed, if any.
+fn warn_dropped(dropped: &[Chunk]) {
+    if dropped.is_empty() {
+        return
+    }
+    let names: Vec<String> = dropped.iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+    eprintln!(
+        "warning: dropped {} chunk(s) not safe to copy after changing the image data: {} (keep them with --keep-unsafe-chunks)",
+        dropped.len(),
+        names.join(", ")
+    );
+}
+
 pub fn decode(args: &DecodeArgs) -> Result<(), Error> {
-    println!("run decode");
-    let png_bytes = fs::read(&args.file_path)?;
-    // // println!("png bytes {:?}", png_bytes);
-    let png = Png::try_from(png_bytes.as_slice()).unwrap();
-
-    if let Some(chunk) = png.chunk_by_type(&args.chunk_type) {
-        // println!("message");
-        println!("msg: {}", chunk.data_as_string().unwrap());
-        return Ok(())
-    } else {
-        println!("didn't hit");
-       return Err(io::Error::new(
-        io::ErrorKind::NotFound,
-        "Chunk with specified type not found",
-    )
-    .into());
+    if let (MethodArg::Lsb, Some(chunk_type)) = (args.method, &args.chunk_type) {
+        return Err(format!("unexpected argument {:?}, --method lsb takes no chunk type", chunk_type).into())
+    }
+    let options = DecodeOptions {
+        method: args.method.into(),
+        chunk_type: args.chunk_type.clone(),
+        channels: args.channels,
+        bits: args.bits,
+        passphrase: args.passphrase.clone(),
+    };
+    let payload = ops::decode(&read_input(&args.file_path)?, &options)?;
+
+    match &args.out {
+        Some(out) if is_stdio(out) => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&payload.data)?;
+            stdout.flush()?;
+        }
+        Some(out) => {
+            fs::write(out, &payload.data)?;
+            match payload.file_name {
+                Some(name) => eprintln!("wrote {} bytes of {} to {}", payload.data.len(), name, out.display()),
+                None => eprintln!("wrote {} bytes to {}", payload.data.len(), out.display()),
+            }
+        }
+        None => {
+            let message = String::from_utf8(payload.data)
+                .map_err(|_| "message is binary, pass --out <path> to save it")?;
+            println!("msg: {}", message);
+        }
     }
+
     Ok(())
 }
 
 pub fn remove(args: &RemoveArgs) -> Result<(), Error> {
+    let reader = open_png(&args.file_path)?;
+    let selection = if args.all { Selection::All } else { Selection::Nth(args.index.unwrap_or(0)) };
+    let mut seen = 0;
+    let mut removed = 0;
 
-    println!("run remove");
-    let png_bytes = fs::read(&args.file_path)?;
-    let mut png = Png::try_from(png_bytes.as_slice()).unwrap();
-    png.remove_chunk(&args.chunk_type)?;
-    
-    fs::write(&args.file_path, png.as_bytes());
+    rewrite(&args.file_path, args.backup, |writer| {
+        for chunk in reader {
+            let chunk = chunk?;
+            if chunk.chunk_type().to_string() == args.chunk_type {
+                seen += 1;
+                if selection.includes(seen - 1) {
+                    removed += 1;
+                    continue;
+                }
+            }
+            writer.write_chunk(&chunk)?;
+        }
+
+        if removed == 0 {
+            return Err(selection.not_found(&args.chunk_type, seen))
+        }
+        Ok(())
+    })?;
 
+    eprintln!("removed {} chunk(s) of type {}", removed, args.chunk_type);
     Ok(())
 }
 
 pub fn print(args: &PrintArgs) -> Result<(), Error> {
-    let png_bytes = fs::read(&args.file_path)?;
-    let png = Png::try_from(png_bytes.as_slice()).unwrap();
+    let mut reader = open_png(&args.file_path)?;
+
+    if args.format == PrintFormat::Text {
+        println!("File: {:?}", &args.file_path);
+
+        for (i, chunk) in reader.enumerate() {
+            let chunk = chunk?;
+            if i == 0 {
+                match Ihdr::try_from(&chunk) {
+                    Ok(ihdr) => println!("  {}", ihdr),
+                    Err(e) => println!("  IHDR: {}", e),
+                }
+            }
+
+            println!(
+                "  chunk#{}{{ chunk_type: {}, data_length: {}}}",
+                i,
+                chunk.chunk_type(),
+                chunk.length(),
+            );
+        }
+
+        return Ok(())
+    }
+
+    let mut summaries = Vec::new();
+    loop {
+        let offset = reader.offset();
+        match reader.next() {
+            Some(chunk) => summaries.push(chunk_summary(summaries.len(), offset, &chunk?)),
+            None => break,
+        }
+    }
+
+    if args.format == PrintFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&summaries)?);
+    } else {
+        println!("index,type,length,crc,offset,critical,public,safe_to_copy,fields");
+        for summary in &summaries {
+            let fields = match &summary["fields"] {
+                Value::Null => String::new(),
+                fields => format!("\"{}\"", fields.to_string().replace('"', "\"\"")),
+            };
+            println!(
+                "{},{},{},{},{},{},{},{},{}",
+                summary["index"],
+                summary["type"].as_str().unwrap_or_default(),
+                summary["length"],
+                summary["crc"],
+                summary["offset"],
+                summary["critical"],
+                summary["public"],
+                summary["safe_to_copy"],
+                fields,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub fn validate(args: &ValidateArgs) -> Result<(), Error> {
+    let png = create_png_struct(&args.file_path)?;
+    let diagnostics = png.validate();
+
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    if errors > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} breaks {} rule(s) of the PNG spec", args.file_path, errors),
+        )
+        .into())
+    }
+    if diagnostics.is_empty() {
+        println!("{:?} is valid", args.file_path);
+    }
+    Ok(())
+}
+
+pub fn repair(args: &RepairArgs) -> Result<(), Error> {
+    let bytes = read_input(&args.file_path)?;
+    let recovery = Png::recover(&bytes, args.resync);
+
+    for problem in &recovery.problems {
+        eprintln!("{}", problem);
+    }
+    if recovery.png.chunk_by_type("IHDR").is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no IHDR chunk could be salvaged").into())
+    }
+
+    // whatever IEND survived goes, so the file ends with exactly one
+    let mut png = recovery.png;
+    png.remove_chunks_where(|chunk| &chunk.chunk_type().bytes() == b"IEND");
+    png.append_chunk(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+
+    let output = args.output_file.as_ref().unwrap_or(&args.file_path);
+    write_png_with_backup(output, args.backup, &png)?;
+
+    eprintln!("salvaged {} chunk(s) into {}", png.chunks().len(), output.display());
+    Ok(())
+}
+
+pub fn fix_crc(args: &FixCrcArgs) -> Result<(), Error> {
+    let bytes = read_input(&args.file_path)?;
+    let mut png = Png::parse(&bytes, false).map_err(|e| format!("{}: {}", args.file_path.display(), e))?;
+
+    let everything = args.chunk_type.is_empty() && args.index.is_empty();
+    let mut fixed = 0;
+    for (index, chunk) in png.chunks_mut().iter_mut().enumerate() {
+        let selected = args.index.contains(&index) || args.chunk_type.contains(&chunk.chunk_type().to_string());
+        if !everything && !selected {
+            continue
+        }
+
+        let stored = chunk.crc();
+        if chunk.recompute_crc() {
+            eprintln!("chunk #{} ({}): CRC {:#010x} -> {:#010x}", index, chunk.chunk_type(), stored, chunk.crc());
+            fixed += 1;
+        }
+    }
+
+    // a pipeline still needs the image on stdout
+    if fixed == 0 && args.output_file.is_none() && !is_stdio(&args.file_path) {
+        eprintln!("all CRCs were already correct");
+        return Ok(())
+    }
+    let output = args.output_file.as_ref().unwrap_or(&args.file_path);
+    write_png_with_backup(output, args.backup, &png)?;
+
+    eprintln!("fixed {} CRC(s) in {}", fixed, output.display());
+    Ok(())
+}
+
+pub fn rechunk(args: &RechunkArgs) -> Result<(), Error> {
+    let mut png = create_png_struct(&args.file_path)?;
+    let size = if args.single { Chunk::MAX_LENGTH } else { args.size };
+    let before = png.chunks_by_type("IDAT").count();
+    let after = png.rechunk_idat(size)?;
+
+    let output = args.output_file.as_ref().unwrap_or(&args.file_path);
+    write_png_with_backup(output, args.backup, &png)?;
+    eprintln!("rewrote {} IDAT chunk(s) as {} in {}", before, after, output.display());
+    Ok(())
+}
+
+pub fn strip(args: &StripArgs) -> Result<(), Error> {
+    let policy = StripPolicy::new(args.preset.into(), &args.keep, &args.drop)?;
+    let mut png = create_png_struct(&args.file_path)?;
+    let removed = png.strip(&policy);
+
+    // chunk type, count and bytes, in the order the types first appear
+    let mut report: Vec<(String, usize, usize)> = Vec::new();
+    for chunk in &removed {
+        let name = chunk.chunk_type().to_string();
+        let size = chunk.as_bytes().len();
+        match report.iter_mut().find(|(seen, _, _)| *seen == name) {
+            Some((_, count, bytes)) => {
+                *count += 1;
+                *bytes += size;
+            }
+            None => report.push((name, 1, size)),
+        }
+    }
+    for (name, count, bytes) in &report {
+        eprintln!("  {}: {} chunk(s), {} bytes", name, count, bytes);
+    }
+
+    if removed.is_empty() && args.output_file.is_none() && !is_stdio(&args.file_path) {
+        eprintln!("nothing to strip");
+        return Ok(())
+    }
+    let output = args.output_file.as_ref().unwrap_or(&args.file_path);
+    write_png_with_backup(output, args.backup, &png)?;
+
+    let total: usize = report.iter().map(|(_, _, bytes)| bytes).sum();
+    eprintln!("stripped {} chunk(s), {} bytes, into {}", removed.len(), total, output.display());
+    Ok(())
+}
+
+pub fn capacity(args: &CapacityArgs) -> Result<(), Error> {
+    let png = create_png_struct(&args.file_path)?;
 
     println!("File: {:?}", &args.file_path);
+    println!("  {}", png.header_info()?);
+    for line in capacity_report(&png, args.channels) {
+        println!("  {}", line);
+    }
 
-    for (i, chunk) in png.chunks().iter().enumerate() {
-        println!(
-            "  chunk#{}{{ chunk_type: {}, data_length: {}}}",
-            i,
-            chunk.chunk_type(),
-            chunk.length(),
-        );
+    Ok(())
+}
+
+/// One line per hiding method with the most message bytes it can store.
+fn capacity_report(png: &Png, channels: lsb::Channels) -> Vec<String> {
+    // a split message loses a part header in every chunk, and the part
+    // count is a u32
+    let per_part = (Chunk::MAX_LENGTH - payload::PART_HEADER_LEN) as u64;
+    let mut lines = vec![format!(
+        "chunk: {} bytes in one chunk, {} bytes split into at most {} chunks of {} bytes each",
+        Chunk::MAX_LENGTH,
+        per_part * u32::MAX as u64,
+        u32::MAX,
+        per_part
+    )];
+    for bits in [1, 2, 4] {
+        match lsb::capacity(png, channels, bits) {
+            Ok(bytes) => lines.push(format!("lsb --bits {}: {} bytes, after its {} byte header", bits, bytes, lsb::HEADER_LEN)),
+            Err(e) => lines.push(format!("lsb --bits {}: not possible, {}", bits, e)),
+        }
+    }
+    lines.push(format!(
+        "after IEND: {} bytes, the most a payload header can record; decoders ignore it and editors drop it",
+        u64::MAX - payload::HEADER_LEN as u64
+    ));
+    lines.push(format!(
+        "--passphrase takes {} bytes, --file and --compress take {} bytes plus the file name",
+        crypto::HEADER_LEN + crypto::TAG_LEN,
+        payload::HEADER_LEN
+    ));
+    lines
+}
+
+/// Everything `print --format json|csv` reports about one chunk.
+fn chunk_summary(index: usize, offset: usize, chunk: &Chunk) -> Value {
+    let chunk_type = chunk.chunk_type();
+    json!({
+        "index": index,
+        "type": chunk_type.to_string(),
+        "length": chunk.length(),
+        "crc": chunk.crc(),
+        "offset": offset,
+        "critical": chunk_type.is_critical(),
+        "public": chunk_type.is_public(),
+        "safe_to_copy": chunk_type.is_safe_to_copy(),
+        "fields": decoded_fields(chunk),
+    })
+}
+
+/// The contents of chunk types pngme knows how to read, or null.
+fn decoded_fields(chunk: &Chunk) -> Value {
+    let data = chunk.data();
+    match &chunk.chunk_type().bytes() {
+        b"IHDR" => match Ihdr::try_from(chunk) {
+            Ok(ihdr) => json!({
+                "width": ihdr.width,
+                "height": ihdr.height,
+                "bit_depth": ihdr.bit_depth,
+                "color_type": ihdr.color_type.to_string(),
+                "compression_method": ihdr.compression_method,
+                "filter_method": ihdr.filter_method,
+                "interlace_method": ihdr.interlace_method,
+            }),
+            Err(e) => json!({ "error": e.to_string() }),
+        },
+        b"tEXt" | b"zTXt" | b"iTXt" => match TextChunk::try_from(chunk) {
+            Ok(text) => {
+                let mut fields = json!({ "keyword": text.keyword, "text": text.text });
+                if let TextKind::International { compressed, language_tag, translated_keyword } = text.kind {
+                    fields["compressed"] = json!(compressed);
+                    fields["language_tag"] = json!(language_tag);
+                    fields["translated_keyword"] = json!(translated_keyword);
+                }
+                fields
+            }
+            Err(e) => json!({ "error": e.to_string() }),
+        },
+        b"gAMA" if data.len() == 4 => {
+            json!({ "gamma": u32::from_be_bytes(data.try_into().unwrap()) as f64 / 100000.0 })
+        }
+        b"sRGB" if data.len() == 1 => json!({ "rendering_intent": data[0] }),
+        b"pHYs" if data.len() == 9 => json!({
+            "pixels_per_unit_x": u32::from_be_bytes(data[0..4].try_into().unwrap()),
+            "pixels_per_unit_y": u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            "unit": if data[8] == 1 { "meter" } else { "unknown" },
+        }),
+        _ => Value::Null,
+    }
+}
+
+pub fn text(command: &TextCommands) -> Result<(), Error> {
+    match command {
+        TextCommands::Get(args) => {
+            let png = create_png_struct(&args.file_path)?;
+            let texts = text_chunks(&png)?;
+            let matching: Vec<&TextChunk> = texts.iter().filter(|t| t.keyword == args.keyword).collect();
+
+            if matching.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No text with keyword {:?}", args.keyword),
+                )
+                .into())
+            }
+            for text in matching {
+                println!("{}", text.text);
+            }
+        }
+        TextCommands::Set(args) => {
+            let text = TextChunk::new(&args.keyword, &args.text, text_kind(args))?;
+            let mut png = create_png_struct(&args.file_path)?;
+            remove_keyword(&mut png, &args.keyword);
+
+            png.insert_chunk(text.to_chunk()?, ChunkPosition::BeforeIend);
+            write_png_with_backup(&args.file_path, args.backup, &png)?;
+        }
+        TextCommands::List(args) => {
+            let png = create_png_struct(&args.file_path)?;
+            for text in text_chunks(&png)? {
+                println!("{}", text);
+            }
+        }
+        TextCommands::Remove(args) => {
+            let mut png = create_png_struct(&args.file_path)?;
+            let removed = remove_keyword(&mut png, &args.keyword);
+            if removed == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No text with keyword {:?}", args.keyword),
+                )
+                .into())
+            }
+            write_png_with_backup(&args.file_path, args.backup, &png)?;
+            eprintln!("removed {} text chunk(s)", removed);
+        }
     }
 
     Ok(())
 }
 
-pub fn create_png_struct(file_path: PathBuf) -> Result<Png, Error> {
-    let png_bytes = fs::read(file_path)?;
-     Ok(Png::try_from(png_bytes.as_slice()).unwrap())
+fn text_kind(args: &TextSetArgs) -> TextKind {
+    if args.international || args.language.is_some() || args.translated_keyword.is_some() {
+        TextKind::International {
+            compressed: args.compressed,
+            language_tag: args.language.clone().unwrap_or_default(),
+            translated_keyword: args.translated_keyword.clone().unwrap_or_default(),
+        }
+    } else if args.compressed {
+        TextKind::Compressed
+    } else {
+        TextKind::Text
+    }
+}
+
+/// Decodes every tEXt, zTXt and iTXt chunk in file order.
+fn text_chunks(png: &Png) -> Result<Vec<TextChunk>, Error> {
+    png.chunks()
+        .iter()
+        .filter(|chunk| TextChunk::is_text_type(chunk.chunk_type()))
+        .map(TextChunk::try_from)
+        .collect()
+}
+
+/// Removes the text chunks stored under `keyword` from `png` and returns
+/// how many there were. Text chunks that do not decode are left as they are.
+fn remove_keyword(png: &mut Png, keyword: &str) -> usize {
+    png.remove_chunks_where(|chunk| {
+        TextChunk::is_text_type(chunk.chunk_type()) && TextChunk::try_from(chunk).is_ok_and(|text| text.keyword == keyword)
+    })
+    .len()
+}
+
+/// `-` stands for stdin as an input and for stdout as an output.
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// The whole of `file_path`, or of stdin for `-`.
+fn read_input(file_path: &Path) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    let read = if is_stdio(file_path) {
+        io::stdin().lock().read_to_end(&mut bytes).map(|_| ())
+    } else {
+        fs::read(file_path).map(|contents| bytes = contents)
+    };
+    read.map_err(|e| format!("{}: {}", file_path.display(), e))?;
+    Ok(bytes)
+}
+
+/// Reads and parses the PNG at `file_path`, naming the file in the error
+/// if it is not a well formed PNG.
+pub fn create_png_struct(file_path: &Path) -> Result<Png, Error> {
+    let chunks = open_png(file_path)?
+        .collect::<Result<Vec<Chunk>, Error>>()
+        .map_err(|e| format!("{}: {}", file_path.display(), e))?;
+    Ok(Png::from_chunks(chunks))
+}
+
+/// Opens `file_path`, or stdin for `-`, for reading chunk by chunk.
+pub fn open_png(file_path: &Path) -> Result<PngReader<Box<dyn Read>>, Error> {
+    let input: Box<dyn Read> = if is_stdio(file_path) {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(file_path)?))
+    };
+    PngReader::new(input).map_err(|e| format!("{}: {}", file_path.display(), e).into())
+}
+
+/// Replaces `output` with the chunks of `png`, see `rewrite`.
+fn write_png_with_backup(output: &Path, backup: bool, png: &Png) -> Result<(), Error> {
+    rewrite(output, backup, |writer| {
+        for chunk in png.chunks() {
+            writer.write_chunk(chunk)?;
+        }
+        Ok(())
+    })
+}
+
+/// Writes a new PNG to `output` through `write` without ever leaving a
+/// half written file behind. The data goes to a temporary file in the same
+/// directory, is synced to disk and then renamed over `output`, so a crash
+/// or a full disk leaves either the old file or the new one. `output` may
+/// be the file that is being read. With `backup` the previous contents are
+/// kept next to it as `<name>.bak`. An `output` of `-` is streamed to
+/// stdout instead.
+fn rewrite<F>(output: &Path, backup: bool, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut PngWriter<&mut dyn Write>) -> Result<(), Error>,
+{
+    if is_stdio(output) {
+        return write_chunks(&mut BufWriter::new(io::stdout().lock()), write)
+    }
+
+    let directory = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let temp = tempfile::Builder::new().make_in(directory, create_new_file)?;
+
+    write_chunks(&mut BufWriter::new(temp.as_file()), write)?;
+    temp.as_file().sync_all()?;
+
+    // keep the permissions of the file being replaced
+    if let Ok(metadata) = fs::metadata(output) {
+        fs::set_permissions(temp.path(), metadata.permissions())?;
+        if backup {
+            fs::copy(output, backup_path(output))?;
+        }
+    }
+    temp.persist(output)?;
+
+    // make the rename itself durable
+    #[cfg(unix)]
+    File::open(directory)?.sync_all()?;
+
+    Ok(())
+}
+
+/// Creates `path` with the permissions `fs::write` would give a new file,
+/// i.e. 0666 less the umask, instead of the 0600 of a temporary file.
+fn create_new_file(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o666);
+    options.open(path)
+}
+
+/// Writes the signature and the chunks `write` adds to `output`.
+fn write_chunks<F>(output: &mut dyn Write, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut PngWriter<&mut dyn Write>) -> Result<(), Error>,
+{
+    let mut writer = PngWriter::new(output)?;
+    write(&mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// `image.png` is backed up as `image.png.bak`.
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
 }
 
 
 #[cfg(test)]
 mod test {
     use std::io::Write;
-    use std::path::PathBuf;
-    use std::fs::{File};
-    use std::io;
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
     use tempfile::tempdir;
 
-    use crate::args::{EncodeArgs, DecodeArgs};
-    use crate::commands::{encode, decode};
-    use crate::png::Png;
-    
-    fn get_png() -> Png {
-        return Png::try_from(&PNG_FILE[..]).unwrap();
+    use crate::args::{CapacityArgs, EncodeArgs, DecodeArgs, FixCrcArgs, MethodArg, PositionArg, PresetArg, PrintArgs, PrintFormat, RechunkArgs, RemoveArgs, RepairArgs, StripArgs, TextCommands, TextGetArgs, TextRemoveArgs, TextSetArgs, ValidateArgs};
+    use crate::commands::{capacity, capacity_report, chunk_summary, deflated_length, encode, decode, fix_crc, print, rechunk, remove, repair, rewrite, strip, text, validate};
+    use pngme::chunk::chunk_from_strings;
+    use pngme::lsb::Channels;
+    use pngme::ops::{self, EncodeOptions, DEFAULT_CHUNK_SIZE};
+    use pngme::zlib;
+    use pngme::text_chunk::{TextChunk, TextKind};
+    use pngme::png::{ChunkPosition, Png};
+
+    /// Encoding `message` in place with every option at its default.
+    fn encode_args(file_path: &Path, chunk_type: &str, message: &str) -> EncodeArgs {
+        EncodeArgs {
+            file_path: file_path.to_path_buf(),
+            chunk_type: Some(chunk_type.to_string()),
+            message: Some(message.to_string()),
+            output_file: None,
+            file: None,
+            compress: false,
+            method: MethodArg::Chunk,
+            channels: Channels::from_str("rgb").unwrap(),
+            bits: 1,
+            position: PositionArg::BeforeIend,
+            passphrase: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            keep_unsafe_chunks: false,
+            backup: false,
+        }
     }
 
     #[test]
@@ -109,13 +698,23 @@ mod test {
         let temp_file_path = temp_dir.path().join("test.png");
 
         let mut temp_file = File::create(&temp_file_path).unwrap();
-        temp_file.write_all(&PNG_FILE);
+        temp_file.write_all(&PNG_FILE).unwrap();
 
         let args = EncodeArgs {
             file_path: temp_file_path.clone(), // Use the temporary file path
-            chunk_type: "rust".to_string(),
-            message: "a message".to_string(),
+            chunk_type: Some("rust".to_string()),
+            message: Some("a message".to_string()),
             output_file: None, // Output to the same file for this test
+            file: None,
+            compress: false,
+            method: MethodArg::Chunk,
+            channels: Channels::from_str("rgb").unwrap(),
+            bits: 1,
+            position: PositionArg::BeforeIend,
+            passphrase: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            keep_unsafe_chunks: false,
+            backup: false,
         };
 
         let result = encode(&args);
@@ -124,12 +723,607 @@ mod test {
 
         let args = DecodeArgs {
             file_path: temp_file_path.clone(), // Use the temporary file path
-            chunk_type: "rust".to_string(),
+            chunk_type: Some("rust".to_string()),
+            method: MethodArg::Chunk,
+            channels: Channels::from_str("rgb").unwrap(),
+            bits: 1,
+            passphrase: None,
+            out: None,
         };
 
         let result_decode = decode(&args);
 
         assert!(result_decode.is_ok());
+
+        let png = Png::try_from(std::fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        let last = png.chunks().last().unwrap();
+        assert_eq!(last.chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_encrypted_encode() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let args = EncodeArgs {
+            passphrase: Some("hunter2".to_string()),
+            ..encode_args(&temp_file_path, "ruSt", "a message")
+        };
+        encode(&args).unwrap();
+
+        let png = Png::try_from(std::fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        let payload = png.chunk_by_type("ruSt").unwrap().data();
+        assert!(!payload.windows(9).any(|window| window == b"a message"));
+
+        let decode_with = |passphrase: Option<&str>| decode(&DecodeArgs {
+            file_path: temp_file_path.clone(),
+            chunk_type: Some("ruSt".to_string()),
+            method: MethodArg::Chunk,
+            channels: Channels::from_str("rgb").unwrap(),
+            bits: 1,
+            passphrase: passphrase.map(str::to_string),
+            out: None,
+        });
+
+        assert!(decode_with(Some("hunter2")).is_ok());
+        let error = decode_with(Some("hunter3")).unwrap_err();
+        assert_eq!(error.to_string(), "wrong passphrase or tampered message");
+        assert!(decode_with(None).is_err());
+    }
+
+    #[test]
+    fn test_embed_binary_file() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let secret: Vec<u8> = (0..=255).rev().collect();
+        let secret_path = temp_dir.path().join("secret.bin");
+        std::fs::write(&secret_path, &secret).unwrap();
+        let output_path = temp_dir.path().join("out.png");
+
+        let args = EncodeArgs {
+            message: None,
+            output_file: Some(output_path.clone()),
+            file: Some(secret_path),
+            ..encode_args(&temp_file_path, "ruSt", "")
+        };
+        encode(&args).unwrap();
+
+        let decode_to = |out: Option<PathBuf>| decode(&DecodeArgs {
+            file_path: output_path.clone(),
+            chunk_type: Some("ruSt".to_string()),
+            method: MethodArg::Chunk,
+            channels: Channels::from_str("rgb").unwrap(),
+            bits: 1,
+            passphrase: None,
+            out,
+        });
+
+        let error = decode_to(None).unwrap_err();
+        assert!(error.to_string().contains("--out"));
+
+        let extracted = temp_dir.path().join("extracted.bin");
+        decode_to(Some(extracted.clone())).unwrap();
+        assert_eq!(std::fs::read(extracted).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_lsb_drops_chunks_unsafe_to_copy() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        fs::write(&temp_file_path, PNG_FILE).unwrap();
+        encode(&encode_args(&temp_file_path, "prVT", "tied to the pixels")).unwrap();
+
+        let lsb = |keep_unsafe_chunks: bool| EncodeArgs {
+            chunk_type: None,
+            method: MethodArg::Lsb,
+            keep_unsafe_chunks,
+            ..encode_args(&temp_file_path, "", "hidden")
+        };
+        encode(&lsb(true)).unwrap();
+        let png = Png::try_from(fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        assert!(png.chunk_by_type("prVT").is_some());
+
+        encode(&lsb(false)).unwrap();
+        let png = Png::try_from(fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        assert!(png.chunk_by_type("prVT").is_none());
+        assert!(png.chunk_by_type("RuSt").is_some());
+    }
+
+    #[test]
+    fn test_lsb_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+        let output_path = temp_dir.path().join("out.png");
+
+        let args = EncodeArgs {
+            chunk_type: None,
+            output_file: Some(output_path.clone()),
+            method: MethodArg::Lsb,
+            ..encode_args(&temp_file_path, "", "a hidden message")
+        };
+        encode(&args).unwrap();
+
+        let png = Png::try_from(std::fs::read(&output_path).unwrap().as_slice()).unwrap();
+        let original = Png::try_from(&PNG_FILE[..]).unwrap();
+        let types = |png: &Png| png.chunks().iter().map(|c| c.chunk_type().to_string()).collect::<Vec<_>>();
+        assert_eq!(types(&png), types(&original));
+
+        let decode_with = |method: MethodArg, chunk_type: Option<&str>| decode(&DecodeArgs {
+            file_path: output_path.clone(),
+            chunk_type: chunk_type.map(str::to_string),
+            method,
+            channels: Channels::from_str("rgb").unwrap(),
+            bits: 1,
+            passphrase: None,
+            out: None,
+        });
+        decode_with(MethodArg::Lsb, None).unwrap();
+        assert!(decode_with(MethodArg::Lsb, Some("ruSt")).is_err());
+        assert!(decode_with(MethodArg::Chunk, None).is_err());
+
+        let too_long = EncodeArgs {
+            chunk_type: None,
+            method: MethodArg::Lsb,
+            ..encode_args(&temp_file_path, "", &"x".repeat(1000))
+        };
+        let error = encode(&too_long).unwrap_err();
+        assert!(error.to_string().contains("holds only"));
+
+        let with_chunk_type = EncodeArgs { method: MethodArg::Lsb, ..encode_args(&temp_file_path, "ruSt", "hi") };
+        assert!(encode(&with_chunk_type).is_err());
+    }
+
+    #[test]
+    fn test_compressed_encode() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let message = "a message that repeats ".repeat(100);
+        let args = EncodeArgs { compress: true, ..encode_args(&temp_file_path, "ruSt", &message) };
+        encode(&args).unwrap();
+
+        let png = Png::try_from(std::fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        let stored = png.chunk_by_type("ruSt").unwrap().data();
+        assert!(stored.len() < message.len() / 10);
+
+        let out = temp_dir.path().join("message.txt");
+        decode(&DecodeArgs {
+            file_path: temp_file_path.clone(),
+            chunk_type: Some("ruSt".to_string()),
+            method: MethodArg::Chunk,
+            channels: Channels::from_str("rgb").unwrap(),
+            bits: 1,
+            passphrase: None,
+            out: Some(out.clone()),
+        })
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(out).unwrap(), message);
+    }
+
+    #[test]
+    fn test_deflated_length_leaves_out_headers() {
+        let message = b"short";
+        let deflated = zlib::compress(message, 9).len();
+        for (file_name, passphrase) in [(None, None), (Some("notes.txt"), Some("secret"))] {
+            let options = EncodeOptions {
+                compress: true,
+                file_name: file_name.map(str::to_string),
+                passphrase: passphrase.map(str::to_string),
+                ..EncodeOptions::default()
+            };
+            let stored = ops::pack(message, &options).unwrap();
+            assert_eq!(deflated_length(stored.len(), &options), deflated);
+        }
+    }
+
+    #[test]
+    fn test_capacity() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let args = CapacityArgs { file_path: temp_file_path, channels: Channels::from_str("rgb").unwrap() };
+        capacity(&args).unwrap();
+
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let report = capacity_report(&png, Channels::from_str("rgb").unwrap());
+        assert_eq!(
+            report[0],
+            "chunk: 2147483647 bytes in one chunk, 9223371978872717325 bytes split into at most 4294967295 chunks of 2147483635 bytes each"
+        );
+        assert_eq!(report[1], "lsb --bits 1: 929 bytes, after its 8 byte header");
+        assert_eq!(report[3], "lsb --bits 4: 3742 bytes, after its 8 byte header");
+        assert!(report[4].starts_with("after IEND: 18446744073709551599 bytes"));
+    }
+
+    #[test]
+    fn test_split_message_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let message = "a message long enough to need several chunks ".repeat(5);
+        let args = EncodeArgs {
+            position: PositionArg::AfterIhdr,
+            passphrase: Some("hunter2".to_string()),
+            chunk_size: 64,
+            ..encode_args(&temp_file_path, "ruSt", &message)
+        };
+        encode(&args).unwrap();
+
+        let png = Png::try_from(std::fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        let parts: Vec<_> = png.chunks().iter().filter(|c| c.chunk_type().to_string() == "ruSt").collect();
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|c| c.length() <= 64));
+
+        let args = DecodeArgs {
+            file_path: temp_file_path.clone(),
+            chunk_type: Some("ruSt".to_string()),
+            method: MethodArg::Chunk,
+            channels: Channels::from_str("rgb").unwrap(),
+            bits: 1,
+            passphrase: Some("hunter2".to_string()),
+            out: None,
+        };
+        assert!(decode(&args).is_ok());
+
+        // dropping a part has to be reported, not decoded into garbage
+        let mut png = png;
+        png.remove_chunk("ruSt").unwrap();
+        std::fs::write(&temp_file_path, png.as_bytes()).unwrap();
+        let error = decode(&args).unwrap_err();
+        assert!(error.to_string().contains("parts found"));
+    }
+
+    #[test]
+    fn test_text_set_replace_and_remove() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let set = |value: &str, compressed: bool| TextCommands::Set(TextSetArgs {
+            file_path: temp_file_path.clone(),
+            keyword: "Title".to_string(),
+            text: value.to_string(),
+            compressed,
+            international: false,
+            language: None,
+            translated_keyword: None,
+            backup: false,
+        });
+
+        text(&set("first", false)).unwrap();
+        text(&set("second", true)).unwrap();
+
+        let png = Png::try_from(std::fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        let texts: Vec<TextChunk> = png
+            .chunks()
+            .iter()
+            .filter(|chunk| TextChunk::is_text_type(chunk.chunk_type()))
+            .map(|chunk| TextChunk::try_from(chunk).unwrap())
+            .collect();
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].text, "second");
+        assert_eq!(png.chunks().last().unwrap().chunk_type().to_string(), "IEND");
+
+        let get = TextCommands::Get(TextGetArgs { file_path: temp_file_path.clone(), keyword: "Title".to_string() });
+        assert!(text(&get).is_ok());
+
+        let remove = TextCommands::Remove(TextRemoveArgs { file_path: temp_file_path.clone(), keyword: "Title".to_string(), backup: false });
+        assert!(text(&remove).is_ok());
+        assert!(text(&remove).is_err());
+        assert!(text(&get).is_err());
+    }
+
+    #[test]
+    fn test_text_remove_keeps_undecodable_text_and_backs_up() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(chunk_from_strings("zTXt", "Comment\0\0not zlib").unwrap(), ChunkPosition::BeforeIend);
+        png.insert_chunk(TextChunk::new("Title", "old", TextKind::Text).unwrap().to_chunk().unwrap(), ChunkPosition::BeforeIend);
+        let original = png.as_bytes();
+        fs::write(&temp_file_path, &original).unwrap();
+
+        let remove = TextCommands::Remove(TextRemoveArgs { file_path: temp_file_path.clone(), keyword: "Title".to_string(), backup: true });
+        text(&remove).unwrap();
+
+        let png = Png::try_from(fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        assert!(png.chunk_by_type("tEXt").is_none());
+        assert_eq!(png.chunk_by_type("zTXt").unwrap().data(), b"Comment\0\0not zlib");
+        assert_eq!(fs::read(temp_dir.path().join("test.png.bak")).unwrap(), original);
+    }
+
+    #[test]
+    fn test_remove_rewrites_in_place() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let args = RemoveArgs { file_path: temp_file_path.clone(), chunk_type: "RuSt".to_string(), all: false, index: None, backup: false };
+        remove(&args).unwrap();
+
+        let png = Png::try_from(std::fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        assert!(png.chunk_by_type("RuSt").is_none());
+        assert_eq!(png.chunks().len(), 6);
+
+        // nothing left to remove, and the file is untouched
+        assert!(remove(&args).is_err());
+        assert_eq!(std::fs::read(&temp_file_path).unwrap(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_chunk_summary() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        let ihdr = chunk_summary(0, 8, &png.chunks()[0]);
+        assert_eq!(ihdr["type"], "IHDR");
+        assert_eq!(ihdr["length"], 13);
+        assert_eq!(ihdr["offset"], 8);
+        assert_eq!(ihdr["critical"], true);
+        assert_eq!(ihdr["public"], true);
+        assert_eq!(ihdr["safe_to_copy"], false);
+        assert_eq!(ihdr["fields"]["width"], 50);
+        assert_eq!(ihdr["fields"]["color_type"], "rgba");
+
+        let gama = chunk_summary(2, 0, &png.chunks()[2]);
+        assert_eq!(gama["fields"]["gamma"], 0.45455);
+
+        let private = chunk_summary(5, 0, &png.chunks()[5]);
+        assert_eq!(private["type"], "RuSt");
+        assert_eq!(private["public"], false);
+        assert!(private["fields"].is_null());
+    }
+
+    #[test]
+    fn test_print_formats() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        for format in [PrintFormat::Text, PrintFormat::Json, PrintFormat::Csv] {
+            let args = PrintArgs { file_path: temp_file_path.clone(), format };
+            assert!(print(&args).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_remove_all_and_index() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        for message in ["one", "two", "three"] {
+            encode(&encode_args(&temp_file_path, "ruSt", message)).unwrap();
+        }
+
+        let remove_with = |all: bool, index: Option<usize>| remove(&RemoveArgs {
+            file_path: temp_file_path.clone(),
+            chunk_type: "ruSt".to_string(),
+            all,
+            index,
+            backup: false,
+        });
+        let remaining = || {
+            let png = Png::try_from(std::fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+            png.chunks_by_type("ruSt").map(|c| c.data_as_string().unwrap()).collect::<Vec<_>>()
+        };
+
+        remove_with(false, Some(1)).unwrap();
+        assert_eq!(remaining(), ["one", "three"]);
+
+        assert!(remove_with(false, Some(2)).is_err());
+        assert_eq!(remaining(), ["one", "three"]);
+
+        remove_with(true, None).unwrap();
+        assert!(remaining().is_empty());
+    }
+
+    #[test]
+    fn test_encode_with_backup() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let args = EncodeArgs {
+            backup: true,
+            ..encode_args(&temp_file_path, "ruSt", "a message")
+        };
+        encode(&args).unwrap();
+
+        let backup = std::fs::read(temp_dir.path().join("test.png.bak")).unwrap();
+        assert_eq!(backup, PNG_FILE);
+        assert_ne!(std::fs::read(&temp_file_path).unwrap(), PNG_FILE);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_new_output_gets_default_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+        let output_path = temp_dir.path().join("out.png");
+
+        let args = EncodeArgs {
+            output_file: Some(output_path.clone()),
+            ..encode_args(&temp_file_path, "ruSt", "a message")
+        };
+        encode(&args).unwrap();
+
+        // fs::write leaves the mode to the umask, as any new output should
+        let probe = temp_dir.path().join("probe");
+        fs::write(&probe, b"").unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&output_path), mode(&probe));
+    }
+
+    #[test]
+    fn test_failed_rewrite_leaves_file_alone() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let result = rewrite(&temp_file_path, true, |writer| {
+            writer.write_chunk(&Png::try_from(&PNG_FILE[..]).unwrap().chunks()[0])?;
+            Err("disk full".into())
+        });
+        assert_eq!(result.unwrap_err().to_string(), "disk full");
+
+        assert_eq!(std::fs::read(&temp_file_path).unwrap(), PNG_FILE);
+        let entries = std::fs::read_dir(temp_dir.path()).unwrap().count();
+        assert_eq!(entries, 1);
+    }
+
+    #[test]
+    fn test_corrupted_file_is_an_error() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[41] ^= 0xff;
+        File::create(&temp_file_path).unwrap().write_all(&bytes).unwrap();
+
+        let args = PrintArgs { file_path: temp_file_path, format: PrintFormat::Text };
+        let error = print(&args).unwrap_err();
+
+        assert!(error.to_string().contains("CRC mismatch"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE).unwrap();
+
+        let args = ValidateArgs { file_path: temp_file_path.clone() };
+        validate(&args).unwrap();
+
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(&chunk_from_strings("ruSt", "trailing").unwrap().as_bytes());
+        fs::write(&temp_file_path, bytes).unwrap();
+
+        let error = validate(&args).unwrap_err();
+        assert!(error.to_string().contains("breaks 1 rule(s)"));
+    }
+
+    #[test]
+    fn test_repair_truncated_file() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        let output_path = temp_dir.path().join("repaired.png");
+        File::create(&temp_file_path).unwrap().write_all(&PNG_FILE[..PNG_FILE.len() - 6]).unwrap();
+
+        let args = RepairArgs {
+            file_path: temp_file_path.clone(),
+            output_file: Some(output_path.clone()),
+            resync: false,
+            backup: false,
+        };
+        repair(&args).unwrap();
+
+        // the original is left alone and the repair ends in a fresh IEND
+        assert_eq!(fs::read(&temp_file_path).unwrap().len(), PNG_FILE.len() - 6);
+        let repaired = Png::try_from(fs::read(&output_path).unwrap().as_slice()).unwrap();
+        assert_eq!(repaired.as_bytes(), PNG_FILE);
+    }
+
+    #[test]
+    fn test_fix_crc_selected_chunks() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+
+        // hand edit the sRGB rendering intent and the last byte of the RuSt message
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[41] = 1;
+        let rust_data_end = PNG_FILE.len() - 12 - 4;
+        bytes[rust_data_end - 1] = b'x';
+        fs::write(&temp_file_path, &bytes).unwrap();
+
+        let mut args = FixCrcArgs {
+            file_path: temp_file_path.clone(),
+            output_file: None,
+            chunk_type: vec!["sRGB".to_string()],
+            index: vec![],
+            backup: false,
+        };
+        fix_crc(&args).unwrap();
+        let error = Png::try_from(fs::read(&temp_file_path).unwrap().as_slice()).err().unwrap();
+        assert!(error.to_string().contains("chunk #5 (RuSt)"));
+
+        args.chunk_type.clear();
+        fix_crc(&args).unwrap();
+        let png = Png::try_from(fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        assert_eq!(png.chunks()[1].data(), [1]);
+        assert_eq!(png.chunk_by_type("RuSt").unwrap().data_as_string().unwrap(), "hex");
+    }
+
+    #[test]
+    fn test_strip() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(chunk_from_strings("tEXt", "Author\0someone").unwrap(), ChunkPosition::BeforeIend);
+        png.insert_chunk(chunk_from_strings("prVt", "secret").unwrap(), ChunkPosition::BeforeIend);
+        fs::write(&temp_file_path, png.as_bytes()).unwrap();
+
+        let mut args = StripArgs {
+            file_path: temp_file_path.clone(),
+            output_file: None,
+            preset: PresetArg::Safe,
+            keep: vec![],
+            drop: vec![],
+            backup: false,
+        };
+        strip(&args).unwrap();
+        let png = Png::try_from(fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        assert_eq!(fs::read(&temp_file_path).unwrap(), PNG_FILE);
+        assert!(png.chunk_by_type("pHYs").is_some());
+
+        args.preset = PresetArg::AllAncillary;
+        args.keep = vec!["gAMA".to_string()];
+        args.drop = vec!["sRGB".to_string()];
+        strip(&args).unwrap();
+        let png = Png::try_from(fs::read(&temp_file_path).unwrap().as_slice()).unwrap();
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "gAMA", "IDAT", "RuSt", "IEND"]);
+
+        args.drop = vec!["IDAT".to_string()];
+        assert!(strip(&args).is_err());
+    }
+
+    #[test]
+    fn test_rechunk() {
+        let temp_dir = tempdir().unwrap();
+        let temp_file_path = temp_dir.path().join("test.png");
+        let split_path = temp_dir.path().join("split.png");
+        fs::write(&temp_file_path, PNG_FILE).unwrap();
+
+        let mut args = RechunkArgs {
+            file_path: temp_file_path.clone(),
+            output_file: Some(split_path.clone()),
+            size: 512,
+            single: false,
+            backup: false,
+        };
+        rechunk(&args).unwrap();
+        let png = Png::try_from(fs::read(&split_path).unwrap().as_slice()).unwrap();
+        let idats: Vec<u32> = png.chunks_by_type("IDAT").map(|chunk| chunk.length()).collect();
+        assert_eq!(idats.len(), 10);
+        assert!(idats.iter().all(|&length| length <= 512));
+        assert_eq!(png.chunks().last().unwrap().chunk_type().to_string(), "IEND");
+
+        args.file_path = split_path.clone();
+        args.output_file = None;
+        args.single = true;
+        rechunk(&args).unwrap();
+        assert_eq!(fs::read(&split_path).unwrap(), PNG_FILE);
     }
 
 
@@ -0,0 +1,127 @@
+use crate::Error;
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::io;
+
+/// Marks a payload as encrypted by pngme.
+pub const MAGIC: [u8; 4] = *b"PMEc";
+/// Bumped whenever the header layout, KDF or cipher changes.
+pub const VERSION: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// magic, version, salt and nonce
+pub const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+/// Poly1305 tag appended to the ciphertext
+pub const TAG_LEN: usize = 16;
+
+/// Whether `payload` carries the encrypted payload header.
+pub fn is_encrypted(payload: &[u8]) -> bool {
+    payload.len() >= HEADER_LEN && payload[..MAGIC.len()] == MAGIC
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase`.
+///
+/// The output is `MAGIC | VERSION | salt | nonce | ciphertext | tag`. The
+/// header is authenticated along with the message, so changing any byte
+/// of the payload makes `decrypt` fail.
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut salt).map_err(|e| io::Error::other(e.to_string()))?;
+    getrandom::getrandom(&mut nonce).map_err(|e| io::Error::other(e.to_string()))?;
+
+    let mut payload = Vec::with_capacity(HEADER_LEN + plaintext.len() + TAG_LEN);
+    payload.extend_from_slice(&MAGIC);
+    payload.push(VERSION);
+    payload.extend_from_slice(&salt);
+    payload.extend_from_slice(&nonce);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &payload })
+        .map_err(|_| io::Error::other("encryption failed"))?;
+    payload.extend(ciphertext);
+
+    Ok(payload)
+}
+
+/// Reverses `encrypt`. A wrong passphrase and a modified payload are
+/// indistinguishable and both give the same error.
+pub fn decrypt(payload: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    if !is_encrypted(payload) {
+        return Err(invalid("message is not encrypted"))
+    }
+    if payload[MAGIC.len()] != VERSION {
+        return Err(invalid(format!("unsupported encryption version {}", payload[MAGIC.len()])))
+    }
+
+    let (header, ciphertext) = payload.split_at(HEADER_LEN);
+    let salt = &header[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| invalid("wrong passphrase or tampered message"))
+}
+
+/// Argon2id with the crate's default cost parameters.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, Error> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(key)
+}
+
+fn invalid<M: Into<String>>(message: M) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into()).into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let payload = encrypt(b"a secret message", "hunter2").unwrap();
+        assert!(is_encrypted(&payload));
+        assert_eq!(payload.len(), HEADER_LEN + 16 + TAG_LEN);
+        assert_eq!(decrypt(&payload, "hunter2").unwrap(), b"a secret message");
+    }
+
+    #[test]
+    fn test_random_salt_and_nonce() {
+        let first = encrypt(b"a secret message", "hunter2").unwrap();
+        let second = encrypt(b"a secret message", "hunter2").unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let payload = encrypt(b"a secret message", "hunter2").unwrap();
+        let error = decrypt(&payload, "hunter3").unwrap_err();
+        assert_eq!(error.to_string(), "wrong passphrase or tampered message");
+    }
+
+    #[test]
+    fn test_tampered_payload() {
+        let payload = encrypt(b"a secret message", "hunter2").unwrap();
+
+        // a byte of the salt, of the nonce and of the ciphertext
+        for index in [MAGIC.len() + 1, HEADER_LEN - 1, HEADER_LEN + 3] {
+            let mut tampered = payload.clone();
+            tampered[index] ^= 1;
+            assert!(decrypt(&tampered, "hunter2").is_err());
+        }
+    }
+
+    #[test]
+    fn test_plaintext_is_not_encrypted() {
+        assert!(!is_encrypted(b"a secret message"));
+        assert!(decrypt(b"a secret message", "hunter2").is_err());
+    }
+}
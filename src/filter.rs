@@ -0,0 +1,360 @@
+use crate::ihdr::Ihdr;
+use crate::Error;
+use std::io;
+
+/// Column start, row start, column step and row step of the seven Adam7
+/// passes.
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// One reduced image: the whole image when it is not interlaced,
+/// otherwise one Adam7 pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pass {
+    pub width: usize,
+    pub height: usize,
+    /// Column and row in the full image of the pass's first pixel
+    pub origin: (usize, usize),
+    /// Columns and rows between the pass's pixels in the full image
+    pub step: (usize, usize),
+}
+
+impl Pass {
+    /// Where pixel (`column`, `row`) of the pass sits in the full image.
+    pub fn position(&self, column: usize, row: usize) -> (usize, usize) {
+        (self.origin.0 + column * self.step.0, self.origin.1 + row * self.step.1)
+    }
+}
+
+/// The reduced images stored in the IDAT stream, in order. Adam7 passes
+/// that hold no pixels are left out, as they are in the stream itself.
+pub fn passes(ihdr: &Ihdr) -> Vec<Pass> {
+    let (width, height) = (ihdr.width as usize, ihdr.height as usize);
+    if !ihdr.is_interlaced() {
+        return vec![Pass { width, height, origin: (0, 0), step: (1, 1) }]
+    }
+
+    ADAM7
+        .iter()
+        .map(|&(x, y, dx, dy)| Pass {
+            width: (width + dx - 1 - x) / dx,
+            height: (height + dy - 1 - y) / dy,
+            origin: (x, y),
+            step: (dx, dy),
+        })
+        .filter(|pass| pass.width > 0 && pass.height > 0)
+        .collect()
+}
+
+/// Bytes in one row of `width` pixels, not counting the filter type byte.
+pub fn row_length(ihdr: &Ihdr, width: usize) -> usize {
+    (width * ihdr.bits_per_pixel()).div_ceil(8)
+}
+
+/// Bytes in the decompressed IDAT stream of an image with header `ihdr`,
+/// filter type bytes included.
+pub fn data_length(ihdr: &Ihdr) -> usize {
+    passes(ihdr)
+        .iter()
+        .map(|pass| pass.height.saturating_mul(row_length(ihdr, pass.width) + 1))
+        .fold(0, usize::saturating_add)
+}
+
+/// How an encoder picks the filter type of each row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// The same filter type, 0 to 4, for every row
+    Fixed(u8),
+    /// For each row the filter type whose output has the smallest sum of
+    /// absolute differences, PNG spec section 12.8
+    MinimumSum,
+}
+
+/// One unfiltered row and the filter type it was stored with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub filter_type: u8,
+    pub bytes: Vec<u8>,
+}
+
+/// The decompressed IDAT stream with the filtering undone, one list of
+/// rows per pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scanlines {
+    pub passes: Vec<Vec<Row>>,
+    /// Distance to the corresponding byte of the previous pixel, at least 1
+    bytes_per_pixel: usize,
+}
+
+impl Scanlines {
+    /// Unfiltered rows of `ihdr`'s passes, as laid out by `passes`, with
+    /// filter types picked by `strategy`.
+    pub fn new(passes: Vec<Vec<Vec<u8>>>, ihdr: &Ihdr, strategy: Strategy) -> Result<Scanlines, Error> {
+        let bytes_per_pixel = ihdr.bits_per_pixel().div_ceil(8);
+        if let Strategy::Fixed(filter_type @ 5..) = strategy {
+            return Err(invalid(format!("unknown filter type {}", filter_type)))
+        }
+
+        let passes = passes
+            .into_iter()
+            .map(|rows| {
+                let mut previous: Option<Vec<u8>> = None;
+                rows.into_iter()
+                    .map(|bytes| {
+                        let filter_type = match strategy {
+                            Strategy::Fixed(filter_type) => filter_type,
+                            Strategy::MinimumSum => minimum_sum(&bytes, previous.as_deref(), bytes_per_pixel),
+                        };
+                        previous = Some(bytes.clone());
+                        Row { filter_type, bytes }
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Scanlines { passes, bytes_per_pixel })
+    }
+
+    /// Undoes the filter of every row of the decompressed IDAT `data`.
+    pub fn unfilter(data: &[u8], ihdr: &Ihdr) -> Result<Scanlines, Error> {
+        let bytes_per_pixel = ihdr.bits_per_pixel().div_ceil(8);
+        let mut remaining = data;
+        let mut unfiltered = Vec::new();
+
+        for pass in passes(ihdr) {
+            let length = row_length(ihdr, pass.width);
+            // the height comes from the file, only trust it as far as the data goes
+            let mut rows: Vec<Row> = Vec::with_capacity(pass.height.min(remaining.len() / (length + 1)));
+
+            for _ in 0..pass.height {
+                if remaining.len() < length + 1 {
+                    return Err(invalid("image data ends before the last row"))
+                }
+                let (row, rest) = remaining.split_at(length + 1);
+                remaining = rest;
+
+                let filter_type = row[0];
+                let mut bytes = row[1..].to_vec();
+                let previous = rows.last().map(|r| r.bytes.as_slice());
+                unfilter_row(filter_type, &mut bytes, previous, bytes_per_pixel)?;
+                rows.push(Row { filter_type, bytes });
+            }
+            unfiltered.push(rows);
+        }
+
+        if !remaining.is_empty() {
+            return Err(invalid(format!("{} bytes of image data follow the last row", remaining.len())))
+        }
+        Ok(Scanlines { passes: unfiltered, bytes_per_pixel })
+    }
+
+    /// Filters every row again with the filter type it was read with and
+    /// returns the stream ready to be compressed.
+    pub fn filter(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for rows in &self.passes {
+            for (index, row) in rows.iter().enumerate() {
+                let previous = index.checked_sub(1).map(|i| rows[i].bytes.as_slice());
+                data.push(row.filter_type);
+                data.extend(filter_row(row.filter_type, &row.bytes, previous, self.bytes_per_pixel));
+            }
+        }
+        data
+    }
+
+    /// Every row of every pass, in stream order.
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut Row> {
+        self.passes.iter_mut().flatten()
+    }
+}
+
+fn unfilter_row(filter_type: u8, row: &mut [u8], previous: Option<&[u8]>, bpp: usize) -> Result<(), Error> {
+    let up = |i: usize| previous.map_or(0, |p| p[i]);
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let upper_left = if i >= bpp { up(i - bpp) } else { 0 };
+        let predicted = match filter_type {
+            0 => 0,
+            1 => left,
+            2 => up(i),
+            3 => ((left as u16 + up(i) as u16) / 2) as u8,
+            4 => paeth(left, up(i), upper_left),
+            _ => return Err(invalid(format!("unknown filter type {}", filter_type))),
+        };
+        row[i] = row[i].wrapping_add(predicted);
+    }
+    Ok(())
+}
+
+fn filter_row(filter_type: u8, row: &[u8], previous: Option<&[u8]>, bpp: usize) -> Vec<u8> {
+    let up = |i: usize| previous.map_or(0, |p| p[i]);
+    (0..row.len())
+        .map(|i| {
+            let left = if i >= bpp { row[i - bpp] } else { 0 };
+            let upper_left = if i >= bpp { up(i - bpp) } else { 0 };
+            let predicted = match filter_type {
+                1 => left,
+                2 => up(i),
+                3 => ((left as u16 + up(i) as u16) / 2) as u8,
+                4 => paeth(left, up(i), upper_left),
+                _ => 0,
+            };
+            row[i].wrapping_sub(predicted)
+        })
+        .collect()
+}
+
+/// The filter type that leaves `row` with the smallest sum of absolute
+/// differences, reading each filtered byte as a signed value.
+fn minimum_sum(row: &[u8], previous: Option<&[u8]>, bpp: usize) -> u8 {
+    (0..5u8)
+        .min_by_key(|&filter_type| {
+            filter_row(filter_type, row, previous, bpp)
+                .iter()
+                .map(|&byte| (byte as i8).unsigned_abs() as u64)
+                .sum::<u64>()
+        })
+        .unwrap()
+}
+
+/// The predictor of filter type 4, PNG spec section 9.4.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn invalid<M: Into<String>>(message: M) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into()).into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    fn ihdr(width: u32, height: u32, color_type: ColorType, bit_depth: u8, interlace_method: u8) -> Ihdr {
+        Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method,
+        }
+    }
+
+    #[test]
+    fn test_passes() {
+        let plain = ihdr(5, 3, ColorType::Rgb, 8, 0);
+        assert_eq!(passes(&plain), vec![Pass { width: 5, height: 3, origin: (0, 0), step: (1, 1) }]);
+
+        let sizes: Vec<(usize, usize)> = passes(&ihdr(8, 8, ColorType::Rgb, 8, 1))
+            .iter()
+            .map(|pass| (pass.width, pass.height))
+            .collect();
+        assert_eq!(sizes, [(1, 1), (1, 1), (2, 1), (2, 2), (4, 2), (4, 4), (8, 4)]);
+
+        // a single pixel only appears in the first pass
+        assert_eq!(passes(&ihdr(1, 1, ColorType::Rgb, 8, 1)), vec![Pass { width: 1, height: 1, origin: (0, 0), step: (8, 8) }]);
+
+        // the last pass fills the odd rows
+        let last = *passes(&ihdr(8, 8, ColorType::Rgb, 8, 1)).last().unwrap();
+        assert_eq!(last.position(0, 0), (0, 1));
+        assert_eq!(last.position(3, 2), (3, 5));
+    }
+
+    #[test]
+    fn test_row_length() {
+        assert_eq!(row_length(&ihdr(5, 1, ColorType::Rgba, 16, 0), 5), 40);
+        assert_eq!(row_length(&ihdr(5, 1, ColorType::Grayscale, 1, 0), 5), 1);
+        assert_eq!(row_length(&ihdr(9, 1, ColorType::Indexed, 4, 0), 9), 5);
+
+        assert_eq!(data_length(&ihdr(5, 3, ColorType::Rgb, 8, 0)), 3 * 16);
+        assert_eq!(data_length(&ihdr(8, 8, ColorType::Rgb, 8, 1)), 4 + 4 + 7 + 14 + 26 + 52 + 100);
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let header = ihdr(3, 5, ColorType::Rgb, 8, 0);
+        let mut data = Vec::new();
+        for filter_type in 0..5u8 {
+            data.push(filter_type);
+            data.extend((0..9).map(|i| (i * 37 + filter_type as usize * 11) as u8));
+        }
+
+        let scanlines = Scanlines::unfilter(&data, &header).unwrap();
+        assert_eq!(scanlines.passes[0].len(), 5);
+        assert_eq!(scanlines.filter(), data);
+    }
+
+    #[test]
+    fn test_unfilter_known_rows() {
+        let header = ihdr(2, 2, ColorType::Grayscale, 8, 0);
+        // Sub then Up
+        let data = [1, 10, 5, 2, 1, 1];
+        let scanlines = Scanlines::unfilter(&data, &header).unwrap();
+        let rows: Vec<&[u8]> = scanlines.passes[0].iter().map(|r| r.bytes.as_slice()).collect();
+        assert_eq!(rows, [&[10, 15][..], &[11, 16][..]]);
+    }
+
+    #[test]
+    fn test_interlaced_round_trip() {
+        let header = ihdr(8, 8, ColorType::GrayscaleAlpha, 8, 1);
+        let mut data = Vec::new();
+        for pass in passes(&header) {
+            for y in 0..pass.height {
+                data.push((y % 5) as u8);
+                data.extend((0..row_length(&header, pass.width)).map(|x| (x * 7 + y) as u8));
+            }
+        }
+
+        let scanlines = Scanlines::unfilter(&data, &header).unwrap();
+        assert_eq!(scanlines.passes.len(), 7);
+        assert_eq!(scanlines.filter(), data);
+    }
+
+    #[test]
+    fn test_strategies() {
+        let header = ihdr(4, 3, ColorType::Grayscale, 8, 0);
+        let rows = vec![vec![vec![10, 20, 30, 40], vec![10, 20, 30, 40], vec![7, 200, 3, 90]]];
+
+        let fixed = Scanlines::new(rows.clone(), &header, Strategy::Fixed(1)).unwrap();
+        assert!(fixed.passes[0].iter().all(|row| row.filter_type == 1));
+
+        // a ramp is cheapest as Sub, a repeated row as Up
+        let chosen = Scanlines::new(rows.clone(), &header, Strategy::MinimumSum).unwrap();
+        let types: Vec<u8> = chosen.passes[0].iter().map(|row| row.filter_type).collect();
+        assert_eq!(types[..2], [1, 2]);
+
+        let unfiltered = Scanlines::unfilter(&chosen.filter(), &header).unwrap();
+        assert_eq!(unfiltered, chosen);
+        assert!(Scanlines::new(rows, &header, Strategy::Fixed(5)).is_err());
+    }
+
+    #[test]
+    fn test_bad_streams() {
+        let header = ihdr(2, 2, ColorType::Grayscale, 8, 0);
+        assert!(Scanlines::unfilter(&[0, 1, 2], &header).is_err());
+        assert!(Scanlines::unfilter(&[0, 1, 2, 0, 3, 4, 9], &header).is_err());
+        assert!(Scanlines::unfilter(&[5, 1, 2, 0, 3, 4], &header).is_err());
+
+        // a header claiming far more rows than the data holds
+        let tall = ihdr(1, i32::MAX as u32, ColorType::Grayscale, 8, 0);
+        assert!(Scanlines::unfilter(&[0, 1, 0, 2], &tall).is_err());
+    }
+}
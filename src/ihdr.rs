@@ -0,0 +1,261 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// How pixels are laid out, from byte 9 of IHDR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(invalid(format!("unknown color type {}", value))),
+        }
+    }
+}
+
+impl fmt::Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "rgb",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale-alpha",
+            ColorType::Rgba => "rgba",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ColorType {
+    /// The value stored in IHDR.
+    pub fn value(&self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    /// Samples per pixel.
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// The bit depths the spec allows for this color type.
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+/// The decoded contents of the IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: u8,
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if chunk.chunk_type().bytes() != *b"IHDR" {
+            return Err(invalid(format!("expected IHDR, found {}", chunk.chunk_type())))
+        }
+
+        let data = chunk.data();
+        if data.len() != 13 {
+            return Err(invalid(format!("IHDR must have 13 data bytes, found {}", data.len())))
+        }
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+
+        // zero is not a valid dimension and the top bit must stay clear
+        for (name, value) in [("width", width), ("height", height)] {
+            if value == 0 || value > i32::MAX as u32 {
+                return Err(invalid(format!("{} {} is out of range", name, value)))
+            }
+        }
+
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(invalid(format!(
+                "bit depth {} is not allowed for color type {}",
+                bit_depth, color_type
+            )))
+        }
+
+        if data[10] != 0 {
+            return Err(invalid(format!("unknown compression method {}", data[10])))
+        }
+        if data[11] != 0 {
+            return Err(invalid(format!("unknown filter method {}", data[11])))
+        }
+        if data[12] > 1 {
+            return Err(invalid(format!("unknown interlace method {}", data[12])))
+        }
+
+        Ok(Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method: data[10],
+            filter_method: data[11],
+            interlace_method: data[12],
+        })
+    }
+}
+
+impl fmt::Display for Ihdr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "IHDR{{ width: {}, height: {}, bit_depth: {}, color_type: {}, compression: {}, filter: {}, interlace: {}}}",
+            self.width,
+            self.height,
+            self.bit_depth,
+            self.color_type,
+            self.compression_method,
+            self.filter_method,
+            self.interlace_method,
+        )
+    }
+}
+
+impl Ihdr {
+    /// Whether the image is stored with Adam7 interlacing.
+    pub fn is_interlaced(&self) -> bool {
+        self.interlace_method == 1
+    }
+
+    /// Bits used by a single pixel.
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    /// Encodes this header back into an IHDR chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(13);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.extend_from_slice(&[
+            self.bit_depth,
+            self.color_type.value(),
+            self.compression_method,
+            self.filter_method,
+            self.interlace_method,
+        ]);
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+}
+
+fn invalid(message: String) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr_chunk(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Chunk {
+        let data: Vec<u8> = width
+            .to_be_bytes()
+            .iter()
+            .chain(height.to_be_bytes().iter())
+            .chain([bit_depth, color_type, 0, 0, interlace].iter())
+            .copied()
+            .collect();
+
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+
+    #[test]
+    fn test_valid_ihdr() {
+        let ihdr = Ihdr::try_from(&ihdr_chunk(640, 480, 16, 2, 1)).unwrap();
+
+        assert_eq!(ihdr.width, 640);
+        assert_eq!(ihdr.height, 480);
+        assert_eq!(ihdr.bit_depth, 16);
+        assert_eq!(ihdr.color_type, ColorType::Rgb);
+        assert!(ihdr.is_interlaced());
+        assert_eq!(ihdr.bits_per_pixel(), 48);
+    }
+
+    #[test]
+    fn test_bit_depth_color_type_combinations() {
+        let allowed: [(u8, &[u8]); 5] = [
+            (0, &[1, 2, 4, 8, 16]),
+            (2, &[8, 16]),
+            (3, &[1, 2, 4, 8]),
+            (4, &[8, 16]),
+            (6, &[8, 16]),
+        ];
+
+        for (color_type, depths) in allowed {
+            for depth in 1..=16u8 {
+                let parsed = Ihdr::try_from(&ihdr_chunk(1, 1, depth, color_type, 0));
+                assert_eq!(parsed.is_ok(), depths.contains(&depth), "color type {} depth {}", color_type, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_ihdr_fields() {
+        assert!(Ihdr::try_from(&ihdr_chunk(0, 1, 8, 6, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1 << 31, 8, 6, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 8, 5, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 8, 6, 2)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 4, 6, 0)).is_err());
+    }
+
+    #[test]
+    fn test_to_chunk_round_trip() {
+        let chunk = ihdr_chunk(640, 480, 4, 3, 1);
+        assert_eq!(Ihdr::try_from(&chunk).unwrap().to_chunk().as_bytes(), chunk.as_bytes());
+    }
+
+    #[test]
+    fn test_wrong_chunk() {
+        let chunk = Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![0; 13]);
+        assert!(Ihdr::try_from(&chunk).is_err());
+
+        let chunk = Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 12]);
+        assert!(Ihdr::try_from(&chunk).is_err());
+    }
+}
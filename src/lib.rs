@@ -0,0 +1,29 @@
+//! Reading and writing PNG chunks, and hiding messages in them.
+//!
+//! `encode`, `decode` and `remove` work on the bytes of a whole PNG file;
+//! `Png`, `Chunk` and `ChunkType` give access to the chunks themselves.
+
+pub mod chunk;
+pub mod chunk_type;
+pub mod crypto;
+pub mod filter;
+pub mod ihdr;
+pub mod lsb;
+pub mod ops;
+pub mod payload;
+pub mod png;
+pub mod raster;
+pub mod recover;
+pub mod stream;
+pub mod strip;
+pub mod text_chunk;
+pub mod validate;
+pub mod zlib;
+
+pub use chunk::Chunk;
+pub use chunk_type::ChunkType;
+pub use ops::{decode, encode, remove, DecodeOptions, EncodeOptions, Method, Selection};
+pub use png::Png;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
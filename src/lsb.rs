@@ -0,0 +1,303 @@
+use crate::filter::{self, Scanlines};
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+use crate::zlib;
+use crate::Error;
+use std::io;
+use std::str::FromStr;
+
+/// Marks the start of a message hidden in the pixels.
+pub const MAGIC: [u8; 4] = *b"PMEl";
+/// magic and message length (u32)
+pub const HEADER_LEN: usize = MAGIC.len() + 4;
+
+/// The channels whose least significant bits carry the message. The one
+/// sample of a grayscale pixel counts as red, green and blue at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channels {
+    pub red: bool,
+    pub green: bool,
+    pub blue: bool,
+    pub alpha: bool,
+}
+
+impl Default for Channels {
+    /// Red, green and blue, leaving transparency untouched
+    fn default() -> Self {
+        Channels { red: true, green: true, blue: true, alpha: false }
+    }
+}
+
+impl FromStr for Channels {
+    type Err = String;
+
+    /// Any combination of the letters r, g, b and a, e.g. "rgb".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut channels = Channels { red: false, green: false, blue: false, alpha: false };
+        for letter in s.chars() {
+            let flag = match letter {
+                'r' => &mut channels.red,
+                'g' => &mut channels.green,
+                'b' => &mut channels.blue,
+                'a' => &mut channels.alpha,
+                _ => return Err(format!("unknown channel {:?}, use the letters r, g, b and a", letter)),
+            };
+            *flag = true;
+        }
+        if channels == (Channels { red: false, green: false, blue: false, alpha: false }) {
+            return Err("pick at least one channel".to_string())
+        }
+        Ok(channels)
+    }
+}
+
+impl Channels {
+    /// Which samples of a pixel are used, in the order they are stored.
+    fn selected(&self, color_type: ColorType) -> Result<Vec<bool>, Error> {
+        let gray = self.red || self.green || self.blue;
+        let selected = match color_type {
+            ColorType::Grayscale => vec![gray],
+            ColorType::GrayscaleAlpha => vec![gray, self.alpha],
+            ColorType::Rgb => vec![self.red, self.green, self.blue],
+            ColorType::Rgba => vec![self.red, self.green, self.blue, self.alpha],
+            ColorType::Indexed => {
+                return Err(invalid("indexed images cannot hide data, a changed index picks another color"))
+            }
+        };
+        if !selected.contains(&true) {
+            return Err(invalid(format!("none of the chosen channels exist in a {} image", color_type)))
+        }
+        Ok(selected)
+    }
+}
+
+/// How many message bytes fit in the pixels of `png` when `bits` low bits
+/// of each chosen sample are used, not counting the header that is stored
+/// with them.
+pub fn capacity(png: &Png, channels: Channels, bits: u8) -> Result<usize, Error> {
+    check_bits(bits)?;
+    let (ihdr, mut scanlines) = scanlines(png)?;
+    let samples = carriers(&ihdr, &mut scanlines, channels)?.count();
+    Ok((samples * bits as usize / 8).saturating_sub(HEADER_LEN))
+}
+
+/// Hides `data` in the `bits` least significant bits of the chosen samples
+/// and rewrites the IDAT chunks of `png`. More bits hold more data but
+/// change the image more visibly.
+pub fn embed(png: &mut Png, data: &[u8], channels: Channels, bits: u8) -> Result<(), Error> {
+    let available = capacity(png, channels, bits)?;
+    if data.len() > available {
+        return Err(invalid(format!("message needs {} bytes but the image holds only {}", data.len(), available)))
+    }
+    let (ihdr, mut scanlines) = scanlines(png)?;
+
+    let mut message = Vec::with_capacity(HEADER_LEN + data.len());
+    message.extend_from_slice(&MAGIC);
+    message.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    message.extend_from_slice(data);
+
+    let mask = (1u8 << bits) - 1;
+    let message_bits: Vec<u8> = bits_of(&message).collect();
+    let groups = message_bits.chunks(bits as usize).map(|group| group.iter().fold(0, |value, bit| value << 1 | bit));
+    for (byte, value) in carriers(&ihdr, &mut scanlines, channels)?.zip(groups) {
+        *byte = (*byte & !mask) | value;
+    }
+
+    // keep the IDAT chunks no larger than they were
+    let max_chunk_length = png.chunks_by_type("IDAT").map(|c| c.length() as usize).max().unwrap_or(1);
+    let compressed = zlib::compress(&scanlines.filter(), 9);
+    png.replace_image_data(&compressed, max_chunk_length)
+}
+
+/// Reads back a message hidden by `embed` with the same `channels` and
+/// `bits`.
+pub fn extract(png: &Png, channels: Channels, bits: u8) -> Result<Vec<u8>, Error> {
+    check_bits(bits)?;
+    let (ihdr, mut scanlines) = scanlines(png)?;
+    let mut carriers = carriers(&ihdr, &mut scanlines, channels)?;
+    let mask = (1u8 << bits) - 1;
+    let mut next_byte = || -> Option<u8> {
+        (0..8 / bits).try_fold(0u8, |byte, _| Some(byte << bits | (*carriers.next()? & mask)))
+    };
+
+    let mut header = [0u8; HEADER_LEN];
+    for byte in header.iter_mut() {
+        *byte = next_byte().ok_or("the image is too small to hold a message")?;
+    }
+    if header[..MAGIC.len()] != MAGIC {
+        return Err(invalid("no message is hidden in these channels"))
+    }
+
+    let length = u32::from_be_bytes(header[MAGIC.len()..].try_into().unwrap()) as usize;
+    let mut data = Vec::with_capacity(length.min(1 << 20));
+    for _ in 0..length {
+        data.push(next_byte().ok_or_else(|| invalid(format!("hidden message claims {} bytes, more than fit", length)))?);
+    }
+    Ok(data)
+}
+
+fn check_bits(bits: u8) -> Result<(), Error> {
+    if !matches!(bits, 1 | 2 | 4) {
+        return Err(invalid(format!("{} bits per sample is not supported, use 1, 2 or 4", bits)))
+    }
+    Ok(())
+}
+
+/// The unfiltered pixel rows of `png`.
+fn scanlines(png: &Png) -> Result<(Ihdr, Scanlines), Error> {
+    let ihdr = png.header_info()?;
+    if ihdr.bit_depth < 8 {
+        return Err(invalid(format!("{} bit samples are too coarse to hide data in", ihdr.bit_depth)))
+    }
+    let data = zlib::decompress_limited(&png.image_data(), filter::data_length(&ihdr))?;
+    Ok((ihdr, Scanlines::unfilter(&data, &ihdr)?))
+}
+
+/// The bytes holding the least significant bit of every chosen sample, in
+/// stream order. 16 bit samples are big endian, so that is their second
+/// byte.
+fn carriers<'a>(
+    ihdr: &Ihdr,
+    scanlines: &'a mut Scanlines,
+    channels: Channels,
+) -> Result<impl Iterator<Item = &'a mut u8>, Error> {
+    let selected = channels.selected(ihdr.color_type)?;
+    let sample_bytes = ihdr.bit_depth as usize / 8;
+
+    Ok(scanlines.rows_mut().flat_map(move |row| {
+        let selected = selected.clone();
+        row.bytes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| {
+                let sample = i / sample_bytes;
+                i % sample_bytes == sample_bytes - 1 && selected[sample % selected.len()]
+            })
+            .map(|(_, byte)| byte)
+    }))
+}
+
+/// The bits of `bytes`, most significant first.
+fn bits_of(bytes: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    bytes.iter().flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+}
+
+fn invalid<M: Into<String>>(message: M) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into()).into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{chunk_from_strings, Chunk};
+    use crate::chunk_type::ChunkType;
+
+    /// A `width` x `height` image whose samples are a gradient.
+    fn testing_png(width: u32, height: u32, color_type: u8, bit_depth: u8, interlace: u8) -> Png {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        let ihdr_chunk = Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr);
+        let header = Ihdr::try_from(&ihdr_chunk).unwrap();
+
+        let mut raw = Vec::new();
+        for pass in crate::filter::passes(&header) {
+            for y in 0..pass.height {
+                raw.push((y % 5) as u8);
+                raw.extend((0..crate::filter::row_length(&header, pass.width)).map(|x| (x * 3 + y * 7) as u8));
+            }
+        }
+
+        Png::from_chunks(vec![
+            ihdr_chunk,
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), zlib::compress(&raw, 6)),
+            chunk_from_strings("IEND", "").unwrap(),
+        ])
+    }
+
+    fn rgb() -> Channels {
+        Channels::from_str("rgb").unwrap()
+    }
+
+    #[test]
+    fn test_channels_from_str() {
+        let channels = Channels::from_str("ba").unwrap();
+        assert!(!channels.red && !channels.green && channels.blue && channels.alpha);
+        assert!(Channels::from_str("").is_err());
+        assert!(Channels::from_str("rgx").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (color_type, bit_depth, interlace) in [(2, 8, 0), (6, 16, 0), (0, 8, 1), (4, 16, 1)] {
+            let mut png = testing_png(20, 10, color_type, bit_depth, interlace);
+            embed(&mut png, b"a hidden message", rgb(), 1).unwrap();
+
+            let bytes = png.as_bytes();
+            let png = Png::try_from(bytes.as_slice()).unwrap();
+            assert_eq!(extract(&png, rgb(), 1).unwrap(), b"a hidden message", "color type {}", color_type);
+        }
+    }
+
+    #[test]
+    fn test_more_bits_per_sample() {
+        for bits in [2, 4] {
+            let mut png = testing_png(12, 12, 6, 16, 0);
+            let message: Vec<u8> = (0..100).collect();
+            embed(&mut png, &message, rgb(), bits).unwrap();
+            assert_eq!(extract(&png, rgb(), bits).unwrap(), message, "{} bits", bits);
+            assert!(extract(&png, rgb(), 1).is_err());
+        }
+    }
+
+    #[test]
+    fn test_only_least_significant_bits_change() {
+        let original = testing_png(16, 16, 6, 8, 0);
+        let mut png = testing_png(16, 16, 6, 8, 0);
+        embed(&mut png, &[0xff; 20], rgb(), 1).unwrap();
+
+        let (ihdr, before) = scanlines(&original).unwrap();
+        let (_, after) = scanlines(&png).unwrap();
+        let before: Vec<u8> = before.passes.concat().into_iter().flat_map(|row| row.bytes).collect();
+        let after: Vec<u8> = after.passes.concat().into_iter().flat_map(|row| row.bytes).collect();
+
+        assert_eq!(before.len(), after.len());
+        for (i, (a, b)) in before.iter().zip(&after).enumerate() {
+            assert_eq!(a | 1, b | 1);
+            // alpha was not chosen
+            if i % ihdr.color_type.channels() == 3 {
+                assert_eq!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity() {
+        // 10 x 10 RGB pixels give 300 bits with all three channels
+        let png = testing_png(10, 10, 2, 8, 0);
+        assert_eq!(capacity(&png, rgb(), 1).unwrap(), 300 / 8 - HEADER_LEN);
+        assert_eq!(capacity(&png, rgb(), 4).unwrap(), 300 * 4 / 8 - HEADER_LEN);
+        assert_eq!(capacity(&png, Channels::from_str("b").unwrap(), 1).unwrap(), 100 / 8 - HEADER_LEN);
+        assert!(capacity(&png, rgb(), 3).is_err());
+
+        let mut png = png;
+        let error = embed(&mut png, &[0; 30], rgb(), 1).unwrap_err();
+        assert_eq!(error.to_string(), "message needs 30 bytes but the image holds only 29");
+        assert!(embed(&mut png, &[0; 29], rgb(), 1).is_ok());
+    }
+
+    #[test]
+    fn test_nothing_hidden() {
+        let png = testing_png(20, 20, 2, 8, 0);
+        assert!(extract(&png, rgb(), 1).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_images() {
+        assert!(capacity(&testing_png(8, 8, 3, 8, 0), rgb(), 1).is_err());
+        assert!(capacity(&testing_png(8, 8, 0, 4, 0), rgb(), 1).is_err());
+        assert!(capacity(&testing_png(8, 8, 2, 8, 0), Channels::from_str("a").unwrap(), 1).is_err());
+    }
+}
@@ -1,36 +1,33 @@
-use std::{str::FromStr, path::PathBuf};
 use args::PngMeArgs;
 use clap::Parser;
 use args::Commands;
-use commands::{encode, decode, remove, print};
+use commands::{capacity, encode, decode, remove, fix_crc, print, rechunk, repair, strip, text, validate};
+use pngme::Result;
 
 mod args;
-mod chunk;
-mod chunk_type;
 mod commands;
-mod png;
 
-
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
-
-fn main() -> Result<()> {
-    let png_me_args = PngMeArgs::parse();
-    
+fn run(png_me_args: PngMeArgs) -> Result<()> {
     match png_me_args.command {
-        Commands::Encode(args) => {
-            encode(&args);
-        }
-        Commands::Decode(args) => {
-           decode(&args);
-        }
-        Commands::Remove(args) => {
-           remove(&args);
-        }
-        Commands::Print(args) => {
-          print(&args);
-        }
+        Commands::Encode(args) => encode(&args),
+        Commands::Decode(args) => decode(&args),
+        Commands::Remove(args) => remove(&args),
+        Commands::Print(args) => print(&args),
+        Commands::Text(command) => text(&command),
+        Commands::Validate(args) => validate(&args),
+        Commands::Repair(args) => repair(&args),
+        Commands::FixCrc(args) => fix_crc(&args),
+        Commands::Capacity(args) => capacity(&args),
+        Commands::Rechunk(args) => rechunk(&args),
+        Commands::Strip(args) => strip(&args),
     }
+}
 
-    Ok(())
-}
\ No newline at end of file
+fn main() {
+    let png_me_args = PngMeArgs::parse();
+
+    if let Err(e) = run(png_me_args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
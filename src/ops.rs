@@ -0,0 +1,349 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crypto;
+use crate::lsb::{self, Channels};
+use crate::payload::{self, Payload};
+use crate::png::{ChunkPosition, Png};
+use crate::Error;
+use std::io;
+use std::str::FromStr;
+
+/// 1 MiB keeps message chunks small enough for common viewers.
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+/// Where a message is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Method {
+    /// A chunk of its own, visible to anything that lists chunks
+    #[default]
+    Chunk,
+    /// The least significant bits of the pixels
+    Lsb,
+}
+
+/// How `encode` stores a message.
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    pub method: Method,
+    /// Type of the message chunks, required with `Method::Chunk`
+    pub chunk_type: Option<String>,
+    pub position: ChunkPosition,
+    /// Largest chunk to write; longer messages are split across chunks
+    pub chunk_size: usize,
+    pub channels: Channels,
+    /// Low bits of each sample used with `Method::Lsb`: 1, 2 or 4
+    pub bits: u8,
+    /// Deflate the message before storing it
+    pub compress: bool,
+    /// Store the message as a file of this name
+    pub file_name: Option<String>,
+    /// Encrypt the message with a key derived from this passphrase
+    pub passphrase: Option<String>,
+    /// Keep unknown chunks that are not safe to copy when `Method::Lsb`
+    /// changes the image data, see `Png::drop_unsafe_to_copy`
+    pub keep_unsafe_chunks: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            method: Method::Chunk,
+            chunk_type: None,
+            position: ChunkPosition::default(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            channels: Channels::default(),
+            bits: 1,
+            compress: false,
+            file_name: None,
+            passphrase: None,
+            keep_unsafe_chunks: false,
+        }
+    }
+}
+
+/// How `decode` finds a message and opens it.
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    pub method: Method,
+    /// Type of the message chunks, required with `Method::Chunk`
+    pub chunk_type: Option<String>,
+    pub channels: Channels,
+    pub bits: u8,
+    pub passphrase: Option<String>,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            method: Method::Chunk,
+            chunk_type: None,
+            channels: Channels::default(),
+            bits: 1,
+            passphrase: None,
+        }
+    }
+}
+
+/// Which chunks of a type `remove` takes out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// The chunk at this index among the chunks of the type, 0 being the first
+    Nth(usize),
+    All,
+}
+
+impl Selection {
+    /// Whether the `occurrence`-th chunk of the type is taken out.
+    pub fn includes(&self, occurrence: usize) -> bool {
+        match self {
+            Selection::Nth(index) => *index == occurrence,
+            Selection::All => true,
+        }
+    }
+
+    /// The error for a file in which nothing was selected, `seen` being
+    /// the number of chunks of `chunk_type` it has.
+    pub fn not_found(&self, chunk_type: &str, seen: usize) -> Error {
+        let message = match self {
+            Selection::Nth(index) if seen > 0 => {
+                format!("Chunk {} of type {} not found, the file has {}", index, chunk_type, seen)
+            }
+            _ => "Chunk with specified type not found".to_string(),
+        };
+        io::Error::new(io::ErrorKind::NotFound, message).into()
+    }
+}
+
+/// Hides `message` in the PNG file `png` and returns the new file.
+pub fn encode(png: &[u8], message: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, Error> {
+    let mut png = Png::try_from(png)?;
+    let stored = pack(message, options)?;
+
+    match options.method {
+        Method::Chunk => {
+            let chunk_type = options.chunk_type.as_deref().ok_or("a chunk type is required")?;
+            let chunks = message_chunks(&stored, chunk_type, options.chunk_size)?;
+            png.insert_chunks(chunks, options.position);
+        }
+        Method::Lsb => {
+            lsb::embed(&mut png, &stored, options.channels, options.bits)?;
+            if !options.keep_unsafe_chunks {
+                png.drop_unsafe_to_copy();
+            }
+        }
+    }
+    Ok(png.as_bytes())
+}
+
+/// Reads back a message `encode` hid in the PNG file `png`.
+pub fn decode(png: &[u8], options: &DecodeOptions) -> Result<Payload, Error> {
+    let png = Png::try_from(png)?;
+    let stored = match (options.method, &options.chunk_type) {
+        (Method::Lsb, None) => lsb::extract(&png, options.channels, options.bits)?,
+        (Method::Lsb, Some(chunk_type)) => {
+            return Err(format!("unexpected chunk type {:?}, the lsb method takes none", chunk_type).into())
+        }
+        (Method::Chunk, chunk_type) => {
+            chunk_message(&png, chunk_type.as_deref().ok_or("a chunk type is required")?)?
+        }
+    };
+    unpack(stored, options.passphrase.as_deref())
+}
+
+/// Takes the chunks of `chunk_type` picked by `selection` out of the PNG
+/// file `png` and returns the new file and how many chunks were removed.
+pub fn remove(png: &[u8], chunk_type: &str, selection: Selection) -> Result<(Vec<u8>, usize), Error> {
+    let mut png = Png::try_from(png)?;
+    let mut seen = 0;
+    let removed = png
+        .remove_chunks_where(|chunk| {
+            if chunk.chunk_type().to_string() != chunk_type {
+                return false
+            }
+            seen += 1;
+            selection.includes(seen - 1)
+        })
+        .len();
+
+    if removed == 0 {
+        return Err(selection.not_found(chunk_type, seen))
+    }
+    Ok((png.as_bytes(), removed))
+}
+
+/// The bytes `encode` stores for `message`: see `wrap`, then encrypted if
+/// there is a passphrase.
+pub fn pack(message: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, Error> {
+    let data = wrap(message, options)?;
+    match &options.passphrase {
+        Some(passphrase) => crypto::encrypt(&data, passphrase),
+        None => Ok(data),
+    }
+}
+
+/// A file is wrapped in a payload header carrying its name and length, a
+/// message is kept as is unless compression needs the header to flag it or
+/// it starts with `payload::RESERVED_PREFIX` and would pass for a header.
+pub fn wrap(message: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, Error> {
+    if options.file_name.is_none() && !options.compress && !message.starts_with(&payload::RESERVED_PREFIX) {
+        return Ok(message.to_vec())
+    }
+
+    let mut payload = Payload::new(options.file_name.clone(), message.to_vec());
+    payload.compressed = options.compress;
+    payload.as_bytes()
+}
+
+/// Undoes `pack`. Bytes without a payload header come back as a payload
+/// without a file name.
+pub fn unpack(stored: Vec<u8>, passphrase: Option<&str>) -> Result<Payload, Error> {
+    let data = match passphrase {
+        Some(passphrase) => crypto::decrypt(&stored, passphrase)?,
+        None if crypto::is_encrypted(&stored) => {
+            return Err("message is encrypted, a passphrase is needed to decode it".into())
+        }
+        None => stored,
+    };
+
+    if Payload::is_payload(&data) {
+        Payload::try_from(data.as_slice())
+    } else {
+        Ok(Payload::new(None, data))
+    }
+}
+
+/// The chunks of `chunk_type` that carry `stored`, split so none is longer
+/// than `chunk_size`.
+pub fn message_chunks(stored: &[u8], chunk_type: &str, chunk_size: usize) -> Result<Vec<Chunk>, Error> {
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    payload::split(stored, chunk_size.min(Chunk::MAX_LENGTH))?
+        .into_iter()
+        .map(|part| Chunk::try_new(chunk_type.clone(), part))
+        .collect()
+}
+
+/// The data stored in the first chunk of `chunk_type`, joined with the
+/// rest of its parts if it was split.
+pub fn chunk_message(png: &Png, chunk_type: &str) -> Result<Vec<u8>, Error> {
+    let chunk = png.chunk_by_type(chunk_type).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "Chunk with specified type not found",
+        )
+    })?;
+
+    if payload::is_part(chunk.data()) {
+        let parts: Vec<&[u8]> = png
+            .chunks_by_type(chunk_type)
+            .map(|c| c.data())
+            .filter(|data| payload::is_part(data))
+            .collect();
+        payload::join(&parts)
+    } else {
+        Ok(chunk.data().to_vec())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    fn options(chunk_type: &str) -> EncodeOptions {
+        EncodeOptions { chunk_type: Some(chunk_type.to_string()), ..EncodeOptions::default() }
+    }
+
+    fn decode_options(chunk_type: &str) -> DecodeOptions {
+        DecodeOptions { chunk_type: Some(chunk_type.to_string()), ..DecodeOptions::default() }
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let encoded = encode(&PNG_FILE, b"hidden", &options("ruSt")).unwrap();
+        assert_eq!(encoded.len(), PNG_FILE.len() + 12 + 6);
+
+        let payload = decode(&encoded, &decode_options("ruSt")).unwrap();
+        assert_eq!(payload, Payload::new(None, b"hidden".to_vec()));
+    }
+
+    #[test]
+    fn test_message_that_looks_like_a_header() {
+        for magic in [b"PMEp", b"PMEs", b"PMEc", b"PMEl"] {
+            let message = [&magic[..], &[1; 80]].concat();
+            for passphrase in [None, Some("secret".to_string())] {
+                let options = EncodeOptions { passphrase: passphrase.clone(), ..options("ruSt") };
+                let encoded = encode(&PNG_FILE, &message, &options).unwrap();
+
+                let decode_options = DecodeOptions { passphrase, ..decode_options("ruSt") };
+                let payload = decode(&encoded, &decode_options).unwrap();
+                assert_eq!(payload, Payload::new(None, message.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_with_every_option() {
+        let options = EncodeOptions {
+            chunk_size: 64,
+            compress: true,
+            file_name: Some("notes.txt".to_string()),
+            passphrase: Some("secret".to_string()),
+            ..options("ruSt")
+        };
+        let message = b"again and again and again ".repeat(20);
+        let encoded = encode(&PNG_FILE, &message, &options).unwrap();
+
+        let decode_options = DecodeOptions { passphrase: Some("secret".to_string()), ..decode_options("ruSt") };
+        let payload = decode(&encoded, &decode_options).unwrap();
+        assert_eq!(payload.file_name.as_deref(), Some("notes.txt"));
+        assert_eq!(payload.data, message);
+
+        assert!(decode(&encoded, &self::decode_options("ruSt")).is_err());
+    }
+
+    #[test]
+    fn test_lsb_round_trip() {
+        let options = EncodeOptions { method: Method::Lsb, ..EncodeOptions::default() };
+        let encoded = encode(&PNG_FILE, b"in the pixels", &options).unwrap();
+
+        let decode_options = DecodeOptions { method: Method::Lsb, ..DecodeOptions::default() };
+        assert_eq!(decode(&encoded, &decode_options).unwrap().data, b"in the pixels");
+    }
+
+    #[test]
+    fn test_lsb_drops_chunks_unsafe_to_copy() {
+        let png = encode(&PNG_FILE, b"depends on the pixels", &options("prVT")).unwrap();
+        let lsb = EncodeOptions { method: Method::Lsb, ..EncodeOptions::default() };
+
+        let encoded = encode(&png, b"in the pixels", &lsb).unwrap();
+        assert!(Png::try_from(encoded.as_slice()).unwrap().chunk_by_type("prVT").is_none());
+
+        let keep = EncodeOptions { keep_unsafe_chunks: true, ..lsb };
+        let encoded = encode(&png, b"in the pixels", &keep).unwrap();
+        assert!(Png::try_from(encoded.as_slice()).unwrap().chunk_by_type("prVT").is_some());
+    }
+
+    #[test]
+    fn test_missing_chunk_type() {
+        assert!(encode(&PNG_FILE, b"hidden", &EncodeOptions::default()).is_err());
+        assert!(decode(&PNG_FILE, &DecodeOptions::default()).is_err());
+        assert!(decode(&PNG_FILE, &decode_options("ruSt")).is_err());
+    }
+
+    #[test]
+    fn test_remove() {
+        let once = encode(&PNG_FILE, b"one", &options("ruSt")).unwrap();
+        let twice = encode(&once, b"two", &options("ruSt")).unwrap();
+
+        let (bytes, removed) = remove(&twice, "ruSt", Selection::Nth(1)).unwrap();
+        assert_eq!((bytes, removed), (once, 1));
+
+        let (bytes, removed) = remove(&twice, "ruSt", Selection::All).unwrap();
+        assert_eq!((bytes, removed), (PNG_FILE.to_vec(), 2));
+
+        let error = remove(&twice, "ruSt", Selection::Nth(2)).err().unwrap();
+        assert_eq!(error.to_string(), "Chunk 2 of type ruSt not found, the file has 2");
+        assert!(remove(&PNG_FILE, "ruSt", Selection::All).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+use crate::zlib;
+use crate::Error;
+use std::borrow::Cow;
+use std::io;
+
+/// Shared by every header pngme writes. A bare message is never stored
+/// starting with it, so stored data starting with it always has a header.
+pub const RESERVED_PREFIX: [u8; 3] = *b"PME";
+
+/// Marks chunk data that starts with a payload header.
+pub const MAGIC: [u8; 4] = *b"PMEp";
+/// Bumped whenever the header layout changes. Version 1 counted the stored
+/// bytes rather than the message bytes in the data length.
+pub const VERSION: u8 = 2;
+/// magic, version, flags, name length and data length, without the name
+pub const HEADER_LEN: usize = MAGIC.len() + 12;
+
+/// Flag bit set when the stored data is a zlib stream.
+pub const FLAG_COMPRESSED: u8 = 0x01;
+
+/// Deflate stores at most 258 bytes in a 2 bit code, so no stream inflates
+/// to more than this many times its size.
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+/// An embedded file: its raw bytes plus the name it had on disk.
+///
+/// Serialized as `MAGIC | VERSION | flags | name length (u16) | name |
+/// data length (u64) | data`, all integers big endian. The data length
+/// counts the bytes of the message, so before compression if
+/// `FLAG_COMPRESSED` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payload {
+    pub file_name: Option<String>,
+    pub data: Vec<u8>,
+    /// Deflate `data` when serializing
+    pub compressed: bool,
+}
+
+impl Payload {
+    pub fn new(file_name: Option<String>, data: Vec<u8>) -> Payload {
+        Payload { file_name, data, compressed: false }
+    }
+
+    /// Whether `bytes` start with a payload header rather than being a bare
+    /// message.
+    pub fn is_payload(bytes: &[u8]) -> bool {
+        bytes.len() > MAGIC.len() && bytes[..MAGIC.len()] == MAGIC
+    }
+
+    pub fn as_bytes(&self) -> Result<Vec<u8>, Error> {
+        let name = self.file_name.as_deref().unwrap_or("").as_bytes();
+        let name_length = u16::try_from(name.len())
+            .map_err(|_| invalid(format!("file name is {} bytes, at most 65535 fit", name.len())))?;
+
+        let (flags, data) = if self.compressed {
+            (FLAG_COMPRESSED, Cow::Owned(zlib::compress(&self.data, 9)))
+        } else {
+            (0, Cow::Borrowed(self.data.as_slice()))
+        };
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + name.len() + data.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.push(flags);
+        bytes.extend_from_slice(&name_length.to_be_bytes());
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(&(self.data.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&data);
+
+        Ok(bytes)
+    }
+}
+
+impl TryFrom<&[u8]> for Payload {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if !Payload::is_payload(bytes) {
+            return Err(invalid("data has no payload header"))
+        }
+
+        let mut reader = Reader { bytes, pointer: MAGIC.len() };
+        let version = reader.take(1)?[0];
+        if version != VERSION && version != 1 {
+            return Err(invalid(format!("unsupported payload version {}", version)))
+        }
+        let flags = reader.take(1)?[0];
+        if flags & !FLAG_COMPRESSED != 0 {
+            return Err(invalid(format!("unknown payload flags {:#04x}", flags)))
+        }
+
+        let name_length = u16::from_be_bytes(reader.take(2)?.try_into().unwrap()) as usize;
+        let name = String::from_utf8(reader.take(name_length)?.to_vec())?;
+        let data_length = u64::from_be_bytes(reader.take(8)?.try_into().unwrap());
+
+        let stored = reader.rest();
+        let compressed = flags & FLAG_COMPRESSED != 0;
+        let data = match (compressed, version) {
+            (true, 1) => zlib::decompress(stored)?,
+            (true, _) => inflate(stored, data_length)?,
+            (false, _) => stored.to_vec(),
+        };
+        let length = if version == 1 { stored.len() } else { data.len() };
+        if length as u64 != data_length {
+            return Err(invalid(format!("payload header says {} bytes but {} are stored", data_length, length)))
+        }
+
+        let file_name = if name.is_empty() { None } else { Some(name) };
+        Ok(Payload { file_name, data, compressed })
+    }
+}
+
+/// Marks chunk data that is one part of a payload split across chunks.
+pub const PART_MAGIC: [u8; 4] = *b"PMEs";
+/// part magic, part index and part count
+pub const PART_HEADER_LEN: usize = PART_MAGIC.len() + 8;
+
+/// Whether `bytes` are one part of a split payload.
+pub fn is_part(bytes: &[u8]) -> bool {
+    bytes.len() >= PART_HEADER_LEN && bytes[..PART_MAGIC.len()] == PART_MAGIC
+}
+
+/// Splits `data` into the data of as many chunks as needed so that none
+/// is larger than `max_chunk_length`. Data that fits in a single chunk is
+/// returned unchanged; otherwise each part is prefixed with
+/// `PART_MAGIC | index (u32) | count (u32)`.
+pub fn split(data: &[u8], max_chunk_length: usize) -> Result<Vec<Vec<u8>>, Error> {
+    if data.len() <= max_chunk_length {
+        return Ok(vec![data.to_vec()])
+    }
+    if max_chunk_length <= PART_HEADER_LEN {
+        return Err(invalid(format!(
+            "chunk size must be larger than the {} byte part header",
+            PART_HEADER_LEN
+        )))
+    }
+
+    let pieces: Vec<&[u8]> = data.chunks(max_chunk_length - PART_HEADER_LEN).collect();
+    let count = u32::try_from(pieces.len()).map_err(|_| invalid("payload needs too many parts"))?;
+
+    Ok(pieces
+        .into_iter()
+        .enumerate()
+        .map(|(index, piece)| {
+            let mut part = Vec::with_capacity(PART_HEADER_LEN + piece.len());
+            part.extend_from_slice(&PART_MAGIC);
+            part.extend_from_slice(&(index as u32).to_be_bytes());
+            part.extend_from_slice(&count.to_be_bytes());
+            part.extend_from_slice(piece);
+            part
+        })
+        .collect())
+}
+
+/// Reassembles parts made by `split`, in whatever order they were found.
+/// Fails if any part is missing, appears twice or disagrees about the
+/// number of parts.
+pub fn join(parts: &[&[u8]]) -> Result<Vec<u8>, Error> {
+    let mut ordered: Vec<Option<&[u8]>> = Vec::new();
+
+    for part in parts {
+        if !is_part(part) {
+            return Err(invalid("chunk is not part of a split message"))
+        }
+        let index = u32::from_be_bytes(part[4..8].try_into().unwrap()) as usize;
+        let count = u32::from_be_bytes(part[8..12].try_into().unwrap()) as usize;
+
+        if ordered.is_empty() {
+            // every part is a chunk found in the file, so the count cannot be larger
+            if count == 0 || count > parts.len() {
+                return Err(invalid(format!("{} of {} parts found", parts.len(), count)))
+            }
+            ordered = vec![None; count];
+        } else if ordered.len() != count {
+            return Err(invalid(format!(
+                "parts disagree on the part count ({} and {})",
+                ordered.len(),
+                count
+            )))
+        }
+
+        match ordered.get_mut(index) {
+            None => return Err(invalid(format!("part {} is out of range for {} parts", index + 1, count))),
+            Some(Some(_)) => return Err(invalid(format!("part {} of {} appears more than once", index + 1, count))),
+            Some(slot) => *slot = Some(&part[PART_HEADER_LEN..]),
+        }
+    }
+
+    let count = ordered.len();
+    let mut data = Vec::new();
+    for (index, piece) in ordered.into_iter().enumerate() {
+        let piece = piece.ok_or_else(|| invalid(format!("part {} of {} is missing", index + 1, count)))?;
+        data.extend_from_slice(piece);
+    }
+
+    Ok(data)
+}
+
+/// Inflates `stored` to the `length` bytes the header gave, refusing a
+/// length no stream of that size could inflate to before inflating it.
+fn inflate(stored: &[u8], length: u64) -> Result<Vec<u8>, Error> {
+    let limit = usize::try_from(length)
+        .ok()
+        .filter(|_| length <= stored.len() as u64 * MAX_DEFLATE_RATIO)
+        .ok_or_else(|| invalid(format!("payload header says {} bytes, more than {} compressed bytes hold", length, stored.len())))?;
+    zlib::decompress_limited(stored, limit)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pointer: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let end = self.pointer + count;
+        if end > self.bytes.len() {
+            return Err(invalid("payload header is truncated"))
+        }
+        let taken = &self.bytes[self.pointer..end];
+        self.pointer = end;
+        Ok(taken)
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.pointer..]
+    }
+}
+
+fn invalid<M: Into<String>>(message: M) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into()).into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let payload = Payload::new(Some("key.der".to_string()), vec![0, 159, 146, 150, 255]);
+        let bytes = payload.as_bytes().unwrap();
+
+        assert!(Payload::is_payload(&bytes));
+        assert_eq!(Payload::try_from(bytes.as_slice()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_without_file_name() {
+        let payload = Payload::new(None, b"data".to_vec());
+        let bytes = payload.as_bytes().unwrap();
+        assert_eq!(bytes.len(), HEADER_LEN + 4);
+        assert_eq!(Payload::try_from(bytes.as_slice()).unwrap().file_name, None);
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let mut payload = Payload::new(None, "a message that repeats ".repeat(50).into_bytes());
+        payload.compressed = true;
+        let bytes = payload.as_bytes().unwrap();
+
+        assert_eq!(bytes[MAGIC.len() + 1], FLAG_COMPRESSED);
+        assert!(bytes.len() < payload.data.len() / 4);
+        assert_eq!(Payload::try_from(bytes.as_slice()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_unknown_flags() {
+        let mut bytes = Payload::new(None, b"data".to_vec()).as_bytes().unwrap();
+        bytes[MAGIC.len() + 1] = 0x02;
+        assert!(Payload::try_from(bytes.as_slice()).is_err());
+
+        // the compressed flag on data that is not a zlib stream
+        bytes[MAGIC.len() + 1] = FLAG_COMPRESSED;
+        assert!(Payload::try_from(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_length_mismatch() {
+        let mut bytes = Payload::new(None, b"data".to_vec()).as_bytes().unwrap();
+        bytes.pop();
+        assert!(Payload::try_from(bytes.as_slice()).is_err());
+
+        let bytes = Payload::new(Some("name".to_string()), vec![]).as_bytes().unwrap();
+        assert!(Payload::try_from(&bytes[..10]).is_err());
+    }
+
+    #[test]
+    fn test_compressed_length_is_checked() {
+        let mut payload = Payload::new(None, vec![7; 5000]);
+        payload.compressed = true;
+        let bytes = payload.as_bytes().unwrap();
+        let length_at = HEADER_LEN - 8;
+
+        let with_length = |length: u64| {
+            let mut bytes = bytes.clone();
+            bytes[length_at..HEADER_LEN].copy_from_slice(&length.to_be_bytes());
+            Payload::try_from(bytes.as_slice())
+        };
+        assert_eq!(with_length(5000).unwrap(), payload);
+        assert!(with_length(4999).is_err());
+        assert!(with_length(5001).is_err());
+
+        let error = with_length(u64::MAX).unwrap_err();
+        assert!(error.to_string().contains("compressed bytes hold"));
+    }
+
+    #[test]
+    fn test_version_1_counts_stored_bytes() {
+        let mut payload = Payload::new(Some("a.txt".to_string()), b"old ".repeat(40));
+        payload.compressed = true;
+        let mut bytes = payload.as_bytes().unwrap();
+
+        let stored = (bytes.len() - HEADER_LEN - 5) as u64;
+        bytes[MAGIC.len()] = 1;
+        bytes[HEADER_LEN - 3..HEADER_LEN + 5].copy_from_slice(&stored.to_be_bytes());
+        assert_eq!(Payload::try_from(bytes.as_slice()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let data: Vec<u8> = (0..100).collect();
+        let parts = split(&data, PART_HEADER_LEN + 30).unwrap();
+
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|part| is_part(part) && part.len() <= PART_HEADER_LEN + 30));
+
+        let mut shuffled: Vec<&[u8]> = parts.iter().map(Vec::as_slice).collect();
+        shuffled.reverse();
+        assert_eq!(join(&shuffled).unwrap(), data);
+    }
+
+    #[test]
+    fn test_small_data_is_not_split() {
+        let parts = split(b"short", 64).unwrap();
+        assert_eq!(parts, vec![b"short".to_vec()]);
+        assert!(split(&[0; 20], PART_HEADER_LEN).is_err());
+    }
+
+    #[test]
+    fn test_join_missing_and_duplicate_parts() {
+        let data: Vec<u8> = (0..100).collect();
+        let parts = split(&data, PART_HEADER_LEN + 30).unwrap();
+
+        let missing = join(&[&parts[0], &parts[1], &parts[3]]).unwrap_err();
+        assert_eq!(missing.to_string(), "3 of 4 parts found");
+
+        let duplicate = join(&[&parts[0], &parts[1], &parts[1], &parts[2], &parts[3]]).unwrap_err();
+        assert_eq!(duplicate.to_string(), "part 2 of 4 appears more than once");
+
+        let other = split(&data, PART_HEADER_LEN + 50).unwrap();
+        assert!(join(&[&parts[0], &other[1]]).is_err());
+    }
+
+    #[test]
+    fn test_join_huge_part_count() {
+        let part = [&PART_MAGIC[..], &0u32.to_be_bytes(), &u32::MAX.to_be_bytes(), b"hi"].concat();
+        assert_eq!(join(&[&part]).unwrap_err().to_string(), "1 of 4294967295 parts found");
+
+        let part = [&PART_MAGIC[..], &0u32.to_be_bytes(), &0u32.to_be_bytes(), b"hi"].concat();
+        assert!(join(&[&part]).is_err());
+    }
+
+    #[test]
+    fn test_plain_message_is_not_a_payload() {
+        assert!(!Payload::is_payload(b"a message"));
+        assert!(Payload::try_from(&b"a message"[..]).is_err());
+    }
+}
@@ -1,8 +1,13 @@
-use crate::{Error, chunk_type};
+use crate::Error;
+use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
-use crate::chunk::{Chunk, chunk_from_strings};
-use std::{fmt, io};
+use crate::ihdr::Ihdr;
+use crate::raster::{self, Raster, WriteOptions};
+use crate::recover::{self, Recovery};
+use crate::strip::{self, StripPolicy};
+use crate::validate::{self, Diagnostic};
 use std::str::FromStr;
+use std::{fmt, io};
 
 pub struct Png {
     chunks: Vec<Chunk>
@@ -17,42 +22,177 @@ impl fmt::Display for Png {
     }
 }
 
+/// Everything that can go wrong while parsing a PNG byte stream.
+///
+/// `offset` is the absolute byte offset in the input at which the problem
+/// was found and `chunk_index` is the zero based position of the chunk
+/// being read, so a failure can be traced back to the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PngError {
+    /// The first 8 bytes are not the PNG signature
+    BadSignature { found: Vec<u8> },
+    /// Fewer than the 12 bytes needed for length, type and CRC remain
+    TruncatedLength { offset: usize, chunk_index: usize },
+    /// The chunk declares more (or fewer) data bytes than are available
+    TruncatedData {
+        offset: usize,
+        chunk_index: usize,
+        chunk_type: [u8; 4],
+        expected: usize,
+        available: usize,
+    },
+    /// The stored CRC does not match the one computed over type and data
+    CrcMismatch {
+        offset: usize,
+        chunk_index: usize,
+        chunk_type: [u8; 4],
+        expected: u32,
+        actual: u32,
+    },
+    /// A chunk type byte is not an ASCII letter
+    InvalidChunkType {
+        offset: usize,
+        chunk_index: usize,
+        bytes: [u8; 4],
+    },
+}
+
+impl PngError {
+    /// Moves an error reported relative to a single chunk to its absolute
+    /// position inside the whole file.
+    pub(crate) fn at(self, base: usize, index: usize) -> PngError {
+        match self {
+            PngError::BadSignature { found } => PngError::BadSignature { found },
+            PngError::TruncatedLength { offset, .. } => PngError::TruncatedLength {
+                offset: base + offset,
+                chunk_index: index,
+            },
+            PngError::TruncatedData { offset, chunk_type, expected, available, .. } => PngError::TruncatedData {
+                offset: base + offset,
+                chunk_index: index,
+                chunk_type,
+                expected,
+                available,
+            },
+            PngError::CrcMismatch { offset, chunk_type, expected, actual, .. } => PngError::CrcMismatch {
+                offset: base + offset,
+                chunk_index: index,
+                chunk_type,
+                expected,
+                actual,
+            },
+            PngError::InvalidChunkType { offset, bytes, .. } => PngError::InvalidChunkType {
+                offset: base + offset,
+                chunk_index: index,
+                bytes,
+            },
+        }
+    }
+}
+
+fn type_name(bytes: &[u8; 4]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PngError::BadSignature { found } => {
+                write!(f, "bad PNG signature: expected {:?}, found {:?}", Png::STANDARD_HEADER, found)
+            }
+            PngError::TruncatedLength { offset, chunk_index } => write!(
+                f,
+                "chunk #{} at byte {}: truncated, not enough bytes for length, type and CRC",
+                chunk_index, offset
+            ),
+            PngError::TruncatedData { offset, chunk_index, chunk_type, expected, available } => write!(
+                f,
+                "chunk #{} ({}) at byte {}: declares {} data bytes but only {} are available",
+                chunk_index, type_name(chunk_type), offset, expected, available
+            ),
+            PngError::CrcMismatch { offset, chunk_index, chunk_type, expected, actual } => write!(
+                f,
+                "chunk #{} ({}) at byte {}: CRC mismatch, stored {:#010x} but computed {:#010x}",
+                chunk_index, type_name(chunk_type), offset, expected, actual
+            ),
+            PngError::InvalidChunkType { offset, chunk_index, bytes } => write!(
+                f,
+                "chunk #{} at byte {}: invalid chunk type {:?}",
+                chunk_index, offset, bytes
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PngError {}
+
 impl TryFrom<&[u8]> for Png {
-    type Error = &'static str;
+    type Error = PngError;
 
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        let header_bytes = bytes[0..8].to_vec();
-        
-        if header_bytes != Png::STANDARD_HEADER {
-            return Err("Header does not match PNG format")
+        Png::parse(bytes, true)
+    }
+}
+
+impl Png {
+    /// Parses a whole file. With `check_crc` false chunks whose CRC does
+    /// not match are kept with the stored CRC instead of failing, so they
+    /// can be fixed with `Chunk::recompute_crc`.
+    pub fn parse(bytes: &[u8], check_crc: bool) -> Result<Png, PngError> {
+        if bytes.len() < 8 || bytes[0..8] != Png::STANDARD_HEADER {
+            return Err(PngError::BadSignature {
+                found: bytes[..bytes.len().min(8)].to_vec(),
+            })
         }
-        
-        let chunks_bytes = bytes[8..].to_vec();
+
         let mut chunks: Vec<Chunk> = Vec::new();
+        let mut pointer = 8;
 
-        let mut pointer = 0; 
+        while pointer < bytes.len() {
+            let index = chunks.len();
+            let remaining = &bytes[pointer..];
 
-        while pointer < chunks_bytes.len() {
+            // length, chunk type, and Crc (4 bytes each) are not included in the length
+            if remaining.len() < 12 {
+                return Err(PngError::TruncatedLength { offset: pointer, chunk_index: index })
+            }
 
-            let length_bytes = &chunks_bytes[pointer..pointer + 4];
-            let length = u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize;
-            // println!("{}",length);
+            let length = u32::from_be_bytes([remaining[0], remaining[1], remaining[2], remaining[3]]) as usize;
+            if remaining.len() - 12 < length {
+                return Err(PngError::TruncatedData {
+                    offset: pointer + 8,
+                    chunk_index: index,
+                    chunk_type: [remaining[4], remaining[5], remaining[6], remaining[7]],
+                    expected: length,
+                    available: remaining.len() - 12,
+                })
+            }
 
-            // + 12 bytes because length, chunk type, and Crc (4 bytes each) not inluded
-            let chunk_bytes = &chunks_bytes[pointer.. pointer + length + 12];
-            let new_chunk: Chunk = Chunk::try_from(&chunk_bytes.to_vec()).unwrap();
+            let new_chunk = Chunk::parse(&remaining[..length + 12], check_crc).map_err(|e| e.at(pointer, index))?;
 
             chunks.push(new_chunk);
 
             pointer += length + 12;
-
         }
-        // println!("chunks {:?}", chunks);
-        // println!("{}", pointer);
-        Ok(Png{chunks})
+
+        Ok(Png { chunks })
     }
 }
 
+/// Where a new chunk goes relative to the image's own chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkPosition {
+    /// Directly after IHDR
+    AfterIhdr,
+    /// In front of the first IDAT
+    BeforeFirstIdat,
+    /// Directly after the last IDAT
+    AfterLastIdat,
+    /// In front of IEND, so the file still ends with IEND
+    #[default]
+    BeforeIend,
+}
+
 impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
@@ -68,22 +208,128 @@ impl Png {
         &self.chunks
     }
 
-     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        for chunk in &self.chunks {
-            if chunk_type == chunk.chunk_type().to_string() {
-                return Some(chunk)
-            }
+    pub fn chunks_mut(&mut self) -> &mut [Chunk] {
+        &mut self.chunks
+    }
+
+    /// Decodes the IHDR chunk, which the spec requires to come first.
+    pub fn header_info(&self) -> Result<Ihdr, Error> {
+        let first = self.chunks.first().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "PNG has no chunks")
+        })?;
+        Ihdr::try_from(first)
+    }
+
+    /// Decodes the pixels, see `Raster`.
+    pub fn raster(&self) -> Result<Raster, Error> {
+        raster::decode(self)
+    }
+
+    /// A new image holding the pixels of `raster` and no chunks besides
+    /// IHDR, PLTE and tRNS where needed, IDAT and IEND.
+    pub fn from_raster(raster: &Raster, options: &WriteOptions) -> Result<Png, Error> {
+        raster::encode(raster, options)
+    }
+
+    /// Salvages what it can from a damaged or truncated file instead of
+    /// failing on the first problem like `try_from`. Chunks with a wrong CRC
+    /// are kept with the CRC recomputed; anything unreadable ends the scan,
+    /// unless `resync` is set, in which case it is skipped up to the next
+    /// intact chunk.
+    pub fn recover(bytes: &[u8], resync: bool) -> Recovery {
+        recover::recover(bytes, resync)
+    }
+
+    /// Checks the chunk ordering and uniqueness rules of the spec, returning
+    /// every problem found in file order. An empty list means the layout
+    /// is valid.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        validate::validate(self)
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
+        self.chunks_by_type(chunk_type).next()
+    }
+
+    /// Every chunk of `chunk_type`, in file order.
+    pub fn chunks_by_type<'a>(&'a self, chunk_type: &str) -> impl Iterator<Item = &'a Chunk> + 'a {
+        let wanted = chunk_type.as_bytes().to_vec();
+        self.chunks
+            .iter()
+            .filter(move |chunk| chunk.chunk_type().bytes() == wanted.as_slice())
+    }
+
+    /// The compressed image: the data of every IDAT chunk joined together.
+    pub fn image_data(&self) -> Vec<u8> {
+        self.chunks_by_type("IDAT").flat_map(|chunk| chunk.data().iter().copied()).collect()
+    }
+
+    /// Replaces the IDAT chunks with `data` split into chunks of at most
+    /// `max_chunk_length` bytes, placed where the first IDAT was.
+    pub fn replace_image_data(&mut self, data: &[u8], max_chunk_length: usize) -> Result<(), Error> {
+        if max_chunk_length == 0 || max_chunk_length > Chunk::MAX_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("IDAT chunks must hold between 1 and {} bytes", Chunk::MAX_LENGTH),
+            )
+            .into())
+        }
+
+        let idat = ChunkType::from_str("IDAT")?;
+        let chunks: Vec<Chunk> = data
+            .chunks(max_chunk_length)
+            .map(|piece| Chunk::new(idat.clone(), piece.to_vec()))
+            .collect();
+
+        let index = self.insertion_index(ChunkPosition::BeforeFirstIdat);
+        self.chunks.retain(|chunk| chunk.chunk_type() != &idat);
+        self.chunks.splice(index..index, chunks);
+        Ok(())
+    }
+
+    /// Joins the IDAT chunks and splits their data again into chunks of at
+    /// most `max_len` bytes, without recompressing. Every other chunk stays
+    /// where it was. Returns the number of IDAT chunks now in the image.
+    pub fn rechunk_idat(&mut self, max_len: usize) -> Result<usize, Error> {
+        if self.chunk_by_type("IDAT").is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "image has no IDAT chunk").into())
         }
-        None
+        self.replace_image_data(&self.image_data(), max_len)?;
+        Ok(self.chunks_by_type("IDAT").count())
     }
 
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
 
-    pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, Error> {
-        // let removed_chunk: Chunk;
+    /// Inserts `chunk` at `position`. Positions that refer to a chunk the
+    /// image does not have fall back to before IEND, or to the very end if
+    /// there is no IEND either.
+    pub fn insert_chunk(&mut self, chunk: Chunk, position: ChunkPosition) {
+        let index = self.insertion_index(position);
+        self.chunks.insert(index, chunk);
+    }
+
+    /// Inserts `chunks` at `position`, keeping them in the given order.
+    pub fn insert_chunks(&mut self, chunks: Vec<Chunk>, position: ChunkPosition) {
+        let index = self.insertion_index(position);
+        self.chunks.splice(index..index, chunks);
+    }
+
+    fn insertion_index(&self, position: ChunkPosition) -> usize {
+        let first = |name: &str| self.chunks.iter().position(|c| c.chunk_type().bytes() == name.as_bytes());
+        let last = |name: &str| self.chunks.iter().rposition(|c| c.chunk_type().bytes() == name.as_bytes());
+        let before_iend = first("IEND").unwrap_or(self.chunks.len());
 
+        match position {
+            ChunkPosition::AfterIhdr => first("IHDR").map_or(0, |i| i + 1),
+            ChunkPosition::BeforeFirstIdat => first("IDAT").unwrap_or(before_iend),
+            ChunkPosition::AfterLastIdat => last("IDAT").map_or(before_iend, |i| i + 1),
+            ChunkPosition::BeforeIend => before_iend,
+        }
+    }
+
+    pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, Error> {
         let chunk_index = self
         .chunks()
         .iter()
@@ -102,6 +348,34 @@ impl Png {
         }
     }
 
+    /// Removes the ancillary chunks `policy` picks and returns them in file
+    /// order.
+    pub fn strip(&mut self, policy: &StripPolicy) -> Vec<Chunk> {
+        strip::strip(self, policy)
+    }
+
+    /// Removes the unknown ancillary chunks whose safe-to-copy bit is
+    /// clear. They may depend on the critical chunks, so an editor that
+    /// changed those must not copy them (PNG spec 14.2). Returns them in
+    /// file order.
+    pub fn drop_unsafe_to_copy(&mut self) -> Vec<Chunk> {
+        self.remove_chunks_where(|chunk| {
+            let chunk_type = chunk.chunk_type();
+            !chunk_type.is_critical() && !chunk_type.is_safe_to_copy() && !chunk_type.is_known()
+        })
+    }
+
+    /// Removes every chunk for which `predicate` returns true and returns
+    /// them in file order.
+    pub fn remove_chunks_where<F>(&mut self, mut predicate: F) -> Vec<Chunk>
+    where
+        F: FnMut(&Chunk) -> bool,
+    {
+        let (removed, kept) = self.chunks.drain(..).partition(|chunk| predicate(chunk));
+        self.chunks = kept;
+        removed
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(self.header());
@@ -116,21 +390,17 @@ impl Png {
 
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use crate::chunk_type::ChunkType;
-    use crate::chunk::Chunk;
-    use std::str::FromStr;
+    use crate::chunk::{Chunk, chunk_from_strings};
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -138,15 +408,6 @@ mod tests {
         Png::from_chunks(chunks)
     }
 
-    fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk, Error> {
-        use std::str::FromStr;
-
-        let chunk_type = ChunkType::from_str(chunk_type)?;
-        let data: Vec<u8> = data.bytes().collect();
-
-        Ok(Chunk::new(chunk_type, data))
-    }
-
     #[test]
     fn test_from_chunks() {
         let chunks = testing_chunks();
@@ -213,6 +474,76 @@ mod tests {
         assert!(png.is_err());
     }
 
+    fn testing_png_bytes() -> Vec<u8> {
+        Png::STANDARD_HEADER
+            .iter()
+            .copied()
+            .chain(testing_chunks().into_iter().flat_map(|chunk| chunk.as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_short_signature() {
+        let png = Png::try_from(&[137, 80, 78][..]);
+        assert_eq!(png.err(), Some(PngError::BadSignature { found: vec![137, 80, 78] }));
+    }
+
+    #[test]
+    fn test_truncated_length() {
+        let mut bytes = testing_png_bytes();
+        let end = bytes.len();
+        bytes.extend_from_slice(&[0, 0, 0, 5, 82]);
+
+        let png = Png::try_from(bytes.as_ref());
+        assert_eq!(png.err(), Some(PngError::TruncatedLength { offset: end, chunk_index: 3 }));
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let mut bytes = testing_png_bytes();
+        bytes.truncate(bytes.len() - 5);
+
+        match Png::try_from(bytes.as_ref()) {
+            Err(PngError::TruncatedData { chunk_index, chunk_type, expected, available, .. }) => {
+                assert_eq!(chunk_index, 2);
+                assert_eq!(&chunk_type, b"LASt");
+                assert_eq!(expected, 19);
+                assert_eq!(available, 14);
+            }
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_crc_mismatch_reports_position() {
+        let mut bytes = testing_png_bytes();
+        // first data byte of the second chunk
+        let second = 8 + 12 + "I am the first chunk".len();
+        bytes[second + 8] ^= 0xff;
+
+        match Png::try_from(bytes.as_ref()) {
+            Err(PngError::CrcMismatch { offset, chunk_index, chunk_type, expected, actual }) => {
+                assert_eq!(chunk_index, 1);
+                assert_eq!(&chunk_type, b"miDl");
+                assert_eq!(offset, second + 8 + "I am another chunk".len());
+                assert_ne!(expected, actual);
+            }
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_invalid_chunk_type_byte() {
+        let mut bytes = testing_png_bytes();
+        bytes[8 + 6] = b'1';
+
+        let png = Png::try_from(bytes.as_ref());
+        assert_eq!(
+            png.err(),
+            Some(PngError::InvalidChunkType { offset: 8 + 6, chunk_index: 0, bytes: *b"Fr1t" })
+        );
+    }
+
 
     #[test]
     fn test_list_chunks() {
@@ -239,6 +570,47 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_insert_chunk_positions() {
+        let cases = [
+            (ChunkPosition::AfterIhdr, 1),
+            (ChunkPosition::BeforeFirstIdat, 4),
+            (ChunkPosition::AfterLastIdat, 5),
+            (ChunkPosition::BeforeIend, 6),
+        ];
+
+        for (position, index) in cases {
+            let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+            png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), position);
+            assert_eq!(chunk_types(&png)[index], "TeSt", "{:?}", position);
+            assert_eq!(chunk_types(&png).last().unwrap(), "IEND");
+        }
+    }
+
+    #[test]
+    fn test_insert_chunks_keeps_order() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let parts = vec![
+            chunk_from_strings("TeSt", "one").unwrap(),
+            chunk_from_strings("TeSt", "two").unwrap(),
+        ];
+        png.insert_chunks(parts, ChunkPosition::AfterIhdr);
+
+        assert_eq!(png.chunks()[1].data_as_string().unwrap(), "one");
+        assert_eq!(png.chunks()[2].data_as_string().unwrap(), "two");
+    }
+
+    #[test]
+    fn test_insert_chunk_without_iend() {
+        let mut png = testing_png();
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::BeforeIend);
+        assert_eq!(chunk_types(&png), ["FrSt", "miDl", "LASt", "TeSt"]);
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -248,12 +620,97 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "one").unwrap());
+        png.append_chunk(chunk_from_strings("FrSt", "between").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "two").unwrap());
+
+        let texts: Vec<String> = png.chunks_by_type("TeSt").map(|c| c.data_as_string().unwrap()).collect();
+        assert_eq!(texts, ["one", "two"]);
+        assert_eq!(png.chunks_by_type("NoNe").count(), 0);
+    }
+
+    #[test]
+    fn test_remove_chunks_where() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "one").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "two").unwrap());
+
+        let removed = png.remove_chunks_where(|chunk| chunk.chunk_type().to_string() == "TeSt");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[1].data_as_string().unwrap(), "two");
+        assert_eq!(chunk_types(&png), ["FrSt", "miDl", "LASt"]);
+
+        assert!(png.remove_chunks_where(|_| false).is_empty());
+    }
+
+    #[test]
+    fn test_replace_image_data() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let data = png.image_data();
+        assert_eq!(data.len(), png.chunk_by_type("IDAT").unwrap().data().len());
+
+        png.replace_image_data(&data, 1000).unwrap();
+        assert_eq!(chunk_types(&png), ["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "IDAT", "IDAT", "IDAT", "IDAT", "RuSt", "IEND"]);
+        assert_eq!(png.image_data(), data);
+
+        png.replace_image_data(&data, Chunk::MAX_LENGTH).unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE);
+        assert!(png.replace_image_data(&data, 0).is_err());
+    }
+
+    #[test]
+    fn test_rechunk_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let data = png.image_data();
+
+        assert_eq!(png.rechunk_idat(1024).unwrap(), data.len().div_ceil(1024));
+        assert!(png.chunks_by_type("IDAT").all(|chunk| chunk.length() <= 1024));
+        assert_eq!(png.image_data(), data);
+
+        assert_eq!(png.rechunk_idat(Chunk::MAX_LENGTH).unwrap(), 1);
+        assert_eq!(png.as_bytes(), PNG_FILE);
+
+        assert!(png.rechunk_idat(0).is_err());
+        png.remove_chunks_where(|chunk| chunk.chunk_type().to_string() == "IDAT");
+        assert!(png.rechunk_idat(1024).is_err());
+    }
+
+    #[test]
+    fn test_drop_unsafe_to_copy() {
+        let mut png = testing_png();
+        for name in ["tRNS", "prVT", "prVt", "RUST"] {
+            png.insert_chunk(chunk_from_strings(name, "data").unwrap(), ChunkPosition::BeforeIend);
+        }
+
+        let dropped = png.drop_unsafe_to_copy();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].chunk_type().to_string(), "prVT");
+        assert!(png.chunk_by_type("tRNS").is_some());
+        assert!(png.chunk_by_type("prVt").is_some());
+        assert!(png.chunk_by_type("RUST").is_some());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
         assert!(png.is_ok());
     }
 
+    #[test]
+    fn test_header_info() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.header_info().unwrap();
+        assert_eq!((ihdr.width, ihdr.height), (50, 50));
+        assert_eq!(ihdr.bit_depth, 8);
+        assert_eq!(ihdr.color_type, crate::ihdr::ColorType::Rgba);
+        assert!(!ihdr.is_interlaced());
+
+        assert!(testing_png().header_info().is_err());
+    }
+
     #[test]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
@@ -281,7 +738,7 @@ mod tests {
     }
 
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
-    const PNG_FILE: [u8; 4803] = [
+    pub(crate) const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
         6, 0, 0, 0, 30, 63, 136, 177, 0, 0, 0, 1, 115, 82, 71, 66, 0, 174, 206, 28, 233, 0, 0, 0,
         4, 103, 65, 77, 65, 0, 0, 177, 143, 11, 252, 97, 5, 0, 0, 0, 9, 112, 72, 89, 115, 0, 0, 14,
@@ -0,0 +1,598 @@
+use crate::chunk::{chunk_from_strings, Chunk};
+use crate::chunk_type::ChunkType;
+use crate::filter::{self, Scanlines, Strategy};
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+use crate::zlib;
+use crate::Error;
+use std::collections::hash_map::{Entry, HashMap};
+use std::io;
+use std::str::FromStr;
+
+/// libpng's IDAT size, small enough for any decoder.
+pub const DEFAULT_IDAT_SIZE: usize = 8192;
+
+/// The pixels of an image, whatever format it was stored in, as RGBA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Raster {
+    pub width: u32,
+    pub height: u32,
+    /// Color type the image was stored with
+    pub color_type: ColorType,
+    /// Bits per sample of `pixels`: 16 for images stored with 16 bit
+    /// samples, 8 for all others
+    pub bit_depth: u8,
+    /// Rows of RGBA pixels from the top, one byte per sample or two
+    /// big-endian bytes with a `bit_depth` of 16
+    pub pixels: Vec<u8>,
+}
+
+impl Raster {
+    /// Bytes taken by one sample of `pixels`.
+    pub fn bytes_per_sample(&self) -> usize {
+        if self.bit_depth == 16 { 2 } else { 1 }
+    }
+
+    /// The red, green, blue and alpha samples of the pixel at (`x`, `y`).
+    pub fn pixel(&self, x: u32, y: u32) -> [u16; 4] {
+        let size = self.bytes_per_sample();
+        let start = (y as usize * self.width as usize + x as usize) * 4 * size;
+        let mut rgba = [0; 4];
+        for (i, sample) in rgba.iter_mut().enumerate() {
+            let at = start + i * size;
+            *sample = match size {
+                2 => u16::from_be_bytes([self.pixels[at], self.pixels[at + 1]]),
+                _ => self.pixels[at] as u16,
+            };
+        }
+        rgba
+    }
+}
+
+/// How `Png::from_raster` stores the pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOptions {
+    pub filter: Strategy,
+    /// Deflate level from 0 (store) to 9 (best)
+    pub level: u32,
+    /// Largest IDAT chunk to write
+    pub idat_size: usize,
+    /// Store the rows in Adam7 order
+    pub interlace: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions { filter: Strategy::MinimumSum, level: 6, idat_size: DEFAULT_IDAT_SIZE, interlace: false }
+    }
+}
+
+/// Inflates and unfilters the image data of `png`, puts Adam7 passes back
+/// together and expands every pixel to RGBA, see `Png::raster`.
+pub(crate) fn decode(png: &Png) -> Result<Raster, Error> {
+    let ihdr = png.header_info()?;
+    let colors = Colors::new(png, &ihdr)?;
+    let data = zlib::decompress_limited(&png.image_data(), filter::data_length(&ihdr))?;
+    let scanlines = Scanlines::unfilter(&data, &ihdr)?;
+
+    let width = ihdr.width as usize;
+    let size = if ihdr.bit_depth == 16 { 2 } else { 1 };
+    let mut pixels = vec![0; width * ihdr.height as usize * 4 * size];
+
+    for (pass, rows) in filter::passes(&ihdr).iter().zip(&scanlines.passes) {
+        for (row, scanline) in rows.iter().enumerate() {
+            let samples = samples(&scanline.bytes, ihdr.bit_depth);
+            // sub-byte rows are padded to whole bytes, so there may be
+            // more samples than pixels
+            for (column, stored) in samples.chunks(ihdr.color_type.channels()).take(pass.width).enumerate() {
+                let (x, y) = pass.position(column, row);
+                let start = (y * width + x) * 4 * size;
+                for (i, sample) in colors.rgba(stored)?.iter().enumerate() {
+                    let at = start + i * size;
+                    match size {
+                        2 => pixels[at..at + 2].copy_from_slice(&sample.to_be_bytes()),
+                        _ => pixels[at] = *sample as u8,
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(Raster {
+        width: ihdr.width,
+        height: ihdr.height,
+        color_type: ihdr.color_type,
+        bit_depth: size as u8 * 8,
+        pixels,
+    })
+}
+
+/// Builds a PNG holding exactly the pixels of `raster`, see
+/// `Png::from_raster`.
+pub(crate) fn encode(raster: &Raster, options: &WriteOptions) -> Result<Png, Error> {
+    let (width, height) = (raster.width as usize, raster.height as usize);
+    if !matches!(raster.bit_depth, 8 | 16) {
+        return Err(invalid(format!("rasters have 8 or 16 bit samples, not {}", raster.bit_depth)))
+    }
+    if width == 0 || height == 0 {
+        return Err(invalid("the raster has no pixels"))
+    }
+    if raster.pixels.len() != width * height * 4 * raster.bytes_per_sample() {
+        return Err(invalid(format!("{} bytes of pixels do not fit {}x{} RGBA", raster.pixels.len(), width, height)))
+    }
+
+    let pixels: Vec<[u16; 4]> = (0..width * height)
+        .map(|i| raster.pixel((i % width) as u32, (i / width) as u32))
+        .collect();
+    let format = Format::choose(raster, &pixels);
+    let ihdr = Ihdr {
+        width: raster.width,
+        height: raster.height,
+        bit_depth: format.bit_depth,
+        color_type: format.color_type,
+        compression_method: 0,
+        filter_method: 0,
+        interlace_method: options.interlace as u8,
+    };
+
+    let passes = filter::passes(&ihdr)
+        .iter()
+        .map(|pass| {
+            (0..pass.height)
+                .map(|row| {
+                    let values: Vec<u16> = (0..pass.width)
+                        .flat_map(|column| {
+                            let (x, y) = pass.position(column, row);
+                            format.stored(pixels[y * width + x])
+                        })
+                        .collect();
+                    pack(&values, ihdr.bit_depth)
+                })
+                .collect()
+        })
+        .collect();
+    let data = Scanlines::new(passes, &ihdr, options.filter)?.filter();
+
+    let mut chunks = vec![ihdr.to_chunk()];
+    if !format.palette.is_empty() {
+        let plte = format.palette.iter().flat_map(|rgba| rgba[..3].iter().map(|&sample| sample as u8)).collect();
+        chunks.push(Chunk::new(ChunkType::from_str("PLTE").unwrap(), plte));
+
+        // entries after the last translucent one default to opaque
+        let alphas: Vec<u8> = format.palette.iter().map(|rgba| rgba[3] as u8).collect();
+        if let Some(last) = alphas.iter().rposition(|&alpha| alpha != 255) {
+            chunks.push(Chunk::new(ChunkType::from_str("tRNS").unwrap(), alphas[..=last].to_vec()));
+        }
+    }
+    chunks.push(chunk_from_strings("IEND", "")?);
+
+    let mut png = Png::from_chunks(chunks);
+    png.replace_image_data(&zlib::compress(&data, options.level), options.idat_size)?;
+    Ok(png)
+}
+
+/// The color type and bit depth pixels are written with.
+struct Format {
+    color_type: ColorType,
+    bit_depth: u8,
+    /// RGBA of each palette entry of an indexed image
+    palette: Vec<[u16; 4]>,
+    indices: HashMap<[u16; 4], u16>,
+}
+
+impl Format {
+    /// Keeps the color type of `raster` when it can hold `pixels` exactly.
+    /// Otherwise an indexed image with more than 256 colors becomes RGB,
+    /// gray that is no longer gray becomes RGB, and translucent pixels add
+    /// an alpha channel.
+    fn choose(raster: &Raster, pixels: &[[u16; 4]]) -> Format {
+        if raster.color_type == ColorType::Indexed && raster.bit_depth == 8 {
+            let mut palette = Vec::new();
+            let mut indices = HashMap::new();
+            for &pixel in pixels {
+                if let Entry::Vacant(entry) = indices.entry(pixel) {
+                    entry.insert(palette.len() as u16);
+                    palette.push(pixel);
+                }
+                if palette.len() > 256 {
+                    break
+                }
+            }
+            if palette.len() <= 256 {
+                let bit_depth = [1, 2, 4, 8].into_iter().find(|depth| palette.len() <= 1 << depth).unwrap();
+                return Format { color_type: ColorType::Indexed, bit_depth, palette, indices }
+            }
+        }
+
+        let max = if raster.bit_depth == 16 { u16::MAX } else { 255 };
+        let alpha = matches!(raster.color_type, ColorType::GrayscaleAlpha | ColorType::Rgba)
+            || pixels.iter().any(|pixel| pixel[3] != max);
+        let gray = matches!(raster.color_type, ColorType::Grayscale | ColorType::GrayscaleAlpha)
+            && pixels.iter().all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+        let color_type = match (gray, alpha) {
+            (true, false) => ColorType::Grayscale,
+            (true, true) => ColorType::GrayscaleAlpha,
+            (false, false) => ColorType::Rgb,
+            (false, true) => ColorType::Rgba,
+        };
+        Format { color_type, bit_depth: raster.bit_depth, palette: Vec::new(), indices: HashMap::new() }
+    }
+
+    /// The samples `pixel` is stored as.
+    fn stored(&self, pixel: [u16; 4]) -> Vec<u16> {
+        match self.color_type {
+            ColorType::Grayscale => vec![pixel[0]],
+            ColorType::GrayscaleAlpha => vec![pixel[0], pixel[3]],
+            ColorType::Rgb => pixel[..3].to_vec(),
+            ColorType::Rgba => pixel.to_vec(),
+            ColorType::Indexed => vec![self.indices[&pixel]],
+        }
+    }
+}
+
+/// Packs samples into the bytes of an unfiltered row, the reverse of
+/// `samples`.
+fn pack(values: &[u16], bit_depth: u8) -> Vec<u8> {
+    match bit_depth {
+        16 => values.iter().flat_map(|value| value.to_be_bytes()).collect(),
+        8 => values.iter().map(|&value| value as u8).collect(),
+        _ => {
+            let depth = bit_depth as usize;
+            let mut bytes = vec![0; (values.len() * depth).div_ceil(8)];
+            for (i, &value) in values.iter().enumerate() {
+                let bit = i * depth;
+                bytes[bit / 8] |= (value as u8) << (8 - depth - bit % 8);
+            }
+            bytes
+        }
+    }
+}
+
+/// The samples packed into an unfiltered row.
+fn samples(bytes: &[u8], bit_depth: u8) -> Vec<u16> {
+    match bit_depth {
+        16 => bytes.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect(),
+        8 => bytes.iter().map(|&byte| byte as u16).collect(),
+        _ => {
+            let mask = (1u8 << bit_depth) - 1;
+            bytes
+                .iter()
+                .flat_map(|&byte| (0..8 / bit_depth).map(move |i| ((byte >> (8 - bit_depth * (i + 1))) & mask) as u16))
+                .collect()
+        }
+    }
+}
+
+/// Turns the stored samples of a pixel into RGBA, taking PLTE and tRNS
+/// into account.
+struct Colors {
+    color_type: ColorType,
+    bit_depth: u8,
+    /// RGBA of each palette entry, with the alpha from tRNS
+    palette: Vec<[u16; 4]>,
+    /// Samples of the one gray level or color that tRNS makes transparent
+    transparent: Option<Vec<u16>>,
+}
+
+impl Colors {
+    fn new(png: &Png, ihdr: &Ihdr) -> Result<Colors, Error> {
+        let trns = png.chunk_by_type("tRNS").map(|chunk| chunk.data());
+        let mut colors = Colors { color_type: ihdr.color_type, bit_depth: ihdr.bit_depth, palette: Vec::new(), transparent: None };
+
+        match ihdr.color_type {
+            ColorType::Indexed => {
+                let plte = png.chunk_by_type("PLTE").ok_or_else(|| invalid("indexed image has no PLTE chunk"))?;
+                let alphas = trns.unwrap_or_default();
+                colors.palette = plte
+                    .data()
+                    .chunks_exact(3)
+                    .enumerate()
+                    .map(|(i, rgb)| [rgb[0] as u16, rgb[1] as u16, rgb[2] as u16, *alphas.get(i).unwrap_or(&255) as u16])
+                    .collect();
+            }
+            ColorType::Grayscale | ColorType::Rgb => {
+                if let Some(trns) = trns {
+                    let key = samples(trns, 16);
+                    if key.len() < ihdr.color_type.channels() {
+                        return Err(invalid("tRNS chunk is too short for the color type"))
+                    }
+                    colors.transparent = Some(key[..ihdr.color_type.channels()].to_vec());
+                }
+            }
+            ColorType::GrayscaleAlpha | ColorType::Rgba => {}
+        }
+        Ok(colors)
+    }
+
+    /// RGBA at 16 bits for 16 bit images and at 8 bits for all others.
+    fn rgba(&self, stored: &[u16]) -> Result<[u16; 4], Error> {
+        let max = ((1u32 << self.bit_depth) - 1) as u16;
+        let alpha = if self.transparent.as_deref() == Some(stored) { 0 } else { max };
+
+        let rgba = match self.color_type {
+            ColorType::Grayscale => [stored[0], stored[0], stored[0], alpha],
+            ColorType::GrayscaleAlpha => [stored[0], stored[0], stored[0], stored[1]],
+            ColorType::Rgb => [stored[0], stored[1], stored[2], alpha],
+            ColorType::Rgba => [stored[0], stored[1], stored[2], stored[3]],
+            ColorType::Indexed => {
+                return self.palette.get(stored[0] as usize).copied().ok_or_else(|| {
+                    invalid(format!("palette index {} is past the {} PLTE entries", stored[0], self.palette.len()))
+                })
+            }
+        };
+        // stretch 1, 2 and 4 bit gray levels over 0..=255
+        Ok(if self.bit_depth < 8 { rgba.map(|sample| sample * 255 / max) } else { rgba })
+    }
+}
+
+fn invalid<M: Into<String>>(message: M) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into()).into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// An image whose pixel at (x, y) holds the samples `pixel(x, y)`,
+    /// with `extra` chunks between IHDR and IDAT.
+    fn testing_png<F>(size: (u32, u32), color_type: u8, bit_depth: u8, interlace: u8, extra: Vec<Chunk>, pixel: F) -> Png
+    where
+        F: Fn(usize, usize) -> Vec<u16>,
+    {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&size.0.to_be_bytes());
+        ihdr.extend_from_slice(&size.1.to_be_bytes());
+        ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        let ihdr_chunk = Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr);
+        let header = Ihdr::try_from(&ihdr_chunk).unwrap();
+
+        let mut raw = Vec::new();
+        for pass in filter::passes(&header) {
+            for row in 0..pass.height {
+                let values: Vec<u16> = (0..pass.width)
+                    .flat_map(|column| {
+                        let (x, y) = pass.position(column, row);
+                        pixel(x, y)
+                    })
+                    .collect();
+                raw.push(0);
+                raw.extend(pack(&values, bit_depth));
+            }
+        }
+
+        let mut chunks = vec![ihdr_chunk];
+        chunks.extend(extra);
+        chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), zlib::compress(&raw, 6)));
+        chunks.push(chunk_from_strings("IEND", "").unwrap());
+        Png::from_chunks(chunks)
+    }
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_rgb_and_interlacing() {
+        let color = |x: usize, y: usize| vec![x as u16 * 20, y as u16 * 30, (x * y) as u16];
+        let plain = testing_png((9, 7), 2, 8, 0, vec![], color).raster().unwrap();
+        let interlaced = testing_png((9, 7), 2, 8, 1, vec![], color).raster().unwrap();
+
+        assert_eq!(plain, interlaced);
+        assert_eq!((plain.width, plain.height, plain.bit_depth), (9, 7, 8));
+        assert_eq!(plain.color_type, ColorType::Rgb);
+        assert_eq!(plain.pixels.len(), 9 * 7 * 4);
+        assert_eq!(plain.pixel(4, 5), [80, 150, 20, 255]);
+    }
+
+    #[test]
+    fn test_low_bit_depth_gray() {
+        for bit_depth in [1, 2, 4] {
+            let max = (1 << bit_depth) - 1;
+            let png = testing_png((5, 3), 0, bit_depth, 1, vec![], |x, y| vec![((x + y) % (max as usize + 1)) as u16]);
+            let raster = png.raster().unwrap();
+
+            assert_eq!(raster.bit_depth, 8);
+            assert_eq!(raster.pixel(0, 0), [0, 0, 0, 255]);
+            let level = (1 % (max + 1)) * 255 / max;
+            assert_eq!(raster.pixel(1, 0), [level, level, level, 255], "{} bits", bit_depth);
+        }
+    }
+
+    #[test]
+    fn test_gray_alpha_and_transparent_key() {
+        let png = testing_png((2, 1), 4, 8, 0, vec![], |x, _| vec![100, x as u16 * 50]);
+        assert_eq!(png.raster().unwrap().pixel(1, 0), [100, 100, 100, 50]);
+
+        let trns = chunk("tRNS", &[0, 0, 0, 7, 0, 0]);
+        let png = testing_png((2, 1), 2, 8, 0, vec![trns], |x, _| vec![0, 7 * x as u16, 0]);
+        let raster = png.raster().unwrap();
+        assert_eq!(raster.pixel(0, 0), [0, 0, 0, 255]);
+        assert_eq!(raster.pixel(1, 0), [0, 7, 0, 0]);
+    }
+
+    #[test]
+    fn test_sixteen_bit_samples_are_kept() {
+        let png = testing_png((3, 2), 6, 16, 1, vec![], |x, y| vec![0x1234, x as u16 * 0x1000, y as u16, 0xfedc]);
+        let raster = png.raster().unwrap();
+
+        assert_eq!(raster.bit_depth, 16);
+        assert_eq!(raster.pixels.len(), 3 * 2 * 8);
+        assert_eq!(raster.pixel(2, 1), [0x1234, 0x2000, 1, 0xfedc]);
+    }
+
+    #[test]
+    fn test_palette() {
+        let plte = chunk("PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        let trns = chunk("tRNS", &[128]);
+        let png = testing_png((3, 1), 3, 4, 0, vec![plte.clone(), trns], |x, _| vec![x as u16]);
+        let raster = png.raster().unwrap();
+
+        assert_eq!(raster.color_type, ColorType::Indexed);
+        assert_eq!(raster.pixels, [255, 0, 0, 128, 0, 255, 0, 255, 0, 0, 255, 255]);
+
+        let past_the_end = testing_png((1, 1), 3, 8, 0, vec![plte], |_, _| vec![3]);
+        assert!(past_the_end.raster().is_err());
+        assert!(testing_png((1, 1), 3, 8, 0, vec![], |_, _| vec![0]).raster().is_err());
+    }
+
+    #[test]
+    fn test_truncated_image_data() {
+        let mut png = testing_png((4, 4), 2, 8, 0, vec![], |_, _| vec![1, 2, 3]);
+        let mut data = zlib::decompress(&png.image_data()).unwrap();
+        data.truncate(data.len() - 1);
+        png.replace_image_data(&zlib::compress(&data, 6), Chunk::MAX_LENGTH).unwrap();
+        assert!(png.raster().is_err());
+    }
+
+    #[test]
+    fn test_header_taller_than_the_data() {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&1u32.to_be_bytes());
+        ihdr.extend_from_slice(&(i32::MAX as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+        let idat = zlib::compress(&[0, 1, 0, 2], 6);
+        let png = Png::from_chunks(vec![chunk("IHDR", &ihdr), chunk("IDAT", &idat), chunk("IEND", &[])]);
+
+        assert!(png.raster().is_err());
+        assert!(crate::lsb::extract(&png, crate::lsb::Channels::default(), 1).is_err());
+    }
+
+    #[test]
+    fn test_real_image() {
+        let png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let raster = png.raster().unwrap();
+        assert_eq!((raster.width, raster.height, raster.color_type), (50, 50, ColorType::Rgba));
+        assert_eq!(raster.pixels.len(), 50 * 50 * 4);
+    }
+
+    /// Writes `raster` with `options`, reads the file back and returns the
+    /// new raster and header.
+    fn write_and_read(raster: &Raster, options: &WriteOptions) -> (Raster, Ihdr) {
+        let bytes = Png::from_raster(raster, options).unwrap().as_bytes();
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        (png.raster().unwrap(), png.header_info().unwrap())
+    }
+
+    #[test]
+    fn test_write_keeps_format() {
+        let gradient = |x: usize, y: usize| x as u16 * 40 + y as u16;
+        let images = [
+            (0, 2, testing_png((6, 4), 0, 2, 0, vec![], |x, y| vec![((x + y) % 4) as u16])),
+            (0, 16, testing_png((6, 4), 0, 16, 0, vec![], |x, y| vec![gradient(x, y) * 300])),
+            (4, 8, testing_png((6, 4), 4, 8, 1, vec![], |x, y| vec![gradient(x, y), 255])),
+            (2, 8, testing_png((6, 4), 2, 8, 0, vec![], |x, y| vec![gradient(x, y), 3, 4])),
+            (6, 16, testing_png((6, 4), 6, 16, 1, vec![], |x, y| vec![1, gradient(x, y), 2, 1000])),
+        ];
+
+        for (color_type, bit_depth, png) in images {
+            let raster = png.raster().unwrap();
+            let (written, ihdr) = write_and_read(&raster, &WriteOptions::default());
+            assert_eq!(written, raster, "color type {} at {} bits", color_type, bit_depth);
+            assert_eq!(ihdr.color_type.value(), color_type);
+            // 1, 2 and 4 bit samples come back as 8 bit
+            assert_eq!(ihdr.bit_depth, bit_depth.max(8));
+        }
+    }
+
+    #[test]
+    fn test_write_palette() {
+        let plte = chunk("PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        let trns = chunk("tRNS", &[255, 128]);
+        let png = testing_png((5, 5), 3, 8, 0, vec![plte, trns], |x, y| vec![((x * y) % 3) as u16]);
+        let raster = png.raster().unwrap();
+
+        let written = Png::from_raster(&raster, &WriteOptions::default()).unwrap();
+        assert_eq!(written.header_info().unwrap().bit_depth, 2);
+        assert_eq!(written.chunk_by_type("PLTE").unwrap().data().len(), 9);
+        assert_eq!(written.chunk_by_type("tRNS").unwrap().data().len(), 2);
+        assert_eq!(written.raster().unwrap(), raster);
+    }
+
+    #[test]
+    fn test_write_widens_format_when_needed() {
+        let mut raster = testing_png((3, 3), 0, 8, 0, vec![], |x, _| vec![x as u16]).raster().unwrap();
+        // no longer gray, and one pixel translucent
+        raster.pixels[0] = 9;
+        raster.pixels[7] = 100;
+
+        let (written, ihdr) = write_and_read(&raster, &WriteOptions::default());
+        assert_eq!(ihdr.color_type, ColorType::Rgba);
+        assert_eq!(written.pixel(0, 0), [9, 0, 0, 255]);
+        assert_eq!(written.pixel(1, 0), [1, 1, 1, 100]);
+    }
+
+    #[test]
+    fn test_write_options() {
+        let raster = testing_png((40, 30), 2, 8, 0, vec![], |x, y| vec![x as u16, y as u16, (x ^ y) as u16]).raster().unwrap();
+        let options = WriteOptions { filter: Strategy::Fixed(0), level: 0, idat_size: 500, interlace: false };
+        let png = Png::from_raster(&raster, &options).unwrap();
+
+        let idats: Vec<usize> = png.chunks_by_type("IDAT").map(|chunk| chunk.length() as usize).collect();
+        assert!(idats.len() > 1 && idats.iter().all(|&length| length <= 500));
+
+        let data = zlib::decompress(&png.image_data()).unwrap();
+        assert!(data.chunks(1 + 40 * 3).all(|row| row[0] == 0));
+        assert_eq!(png.raster().unwrap(), raster);
+
+        let interlaced = WriteOptions { interlace: true, ..WriteOptions::default() };
+        let (written, ihdr) = write_and_read(&raster, &interlaced);
+        assert!(ihdr.is_interlaced());
+        assert_eq!(written, raster);
+    }
+
+    #[test]
+    fn test_pixel_edits_round_trip() {
+        let png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let mut raster = png.raster().unwrap();
+        for (i, sample) in raster.pixels.iter_mut().enumerate().filter(|(i, _)| i % 4 != 3) {
+            *sample = (*sample & !1) | (i % 7 == 0) as u8;
+        }
+
+        let (written, _) = write_and_read(&raster, &WriteOptions::default());
+        assert_eq!(written, raster);
+    }
+
+    #[test]
+    fn test_write_rejects_bad_rasters() {
+        let raster = Raster { width: 2, height: 2, color_type: ColorType::Rgb, bit_depth: 8, pixels: vec![0; 15] };
+        assert!(Png::from_raster(&raster, &WriteOptions::default()).is_err());
+        let raster = Raster { pixels: vec![0; 16], ..raster };
+        assert!(Png::from_raster(&raster, &WriteOptions::default()).is_ok());
+        assert!(Png::from_raster(&Raster { bit_depth: 4, ..raster.clone() }, &WriteOptions::default()).is_err());
+        assert!(Png::from_raster(&Raster { width: 0, height: 0, pixels: vec![], ..raster }, &WriteOptions::default()).is_err());
+    }
+
+    /// Runs over a copy of PngSuite (http://www.schaik.com/pngsuite/), which
+    /// is not part of the repository: `PNGSUITE_DIR=... cargo test -- --ignored`.
+    /// Every basic image must decode to 32x32 pixels, and the interlaced
+    /// `basi*` version of each format must decode to the same raster as the
+    /// plain `basn*` one.
+    #[test]
+    #[ignore = "needs PngSuite in PNGSUITE_DIR"]
+    fn test_pngsuite() {
+        let directory = std::env::var_os("PNGSUITE_DIR").expect("PNGSUITE_DIR is not set");
+        let decode = |path: &Path| {
+            let bytes = std::fs::read(path).unwrap();
+            Png::try_from(bytes.as_slice()).unwrap().raster()
+        };
+
+        let mut compared = 0;
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if !name.starts_with("basn") || !name.ends_with(".png") {
+                continue
+            }
+            let plain = decode(&path).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!((plain.width, plain.height), (32, 32), "{}", name);
+            let interlaced = path.with_file_name(name.replacen("basn", "basi", 1));
+            if interlaced.exists() {
+                assert_eq!(decode(&interlaced).unwrap(), plain, "{}", name);
+                compared += 1;
+            }
+        }
+        assert!(compared > 0, "no basn*/basi* pairs in PNGSUITE_DIR");
+    }
+}
@@ -0,0 +1,257 @@
+use crate::chunk::Chunk;
+use crate::png::{Png, PngError};
+use std::fmt;
+
+/// Something `Png::recover` had to work around.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The chunk was readable but its CRC was wrong. It was kept with a
+    /// freshly computed CRC.
+    CrcFixed(PngError),
+    /// The chunk could not be read and was dropped. Reading either stopped
+    /// here or skipped ahead to the next plausible chunk.
+    Unreadable(PngError),
+    /// Bytes passed over while looking for the next plausible chunk
+    Skipped { offset: usize, length: usize },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::CrcFixed(e) => write!(f, "{} (kept, CRC fixed)", e),
+            Problem::Unreadable(e) => write!(f, "{} (dropped)", e),
+            Problem::Skipped { offset, length } => {
+                write!(f, "skipped {} unreadable bytes at byte {}", length, offset)
+            }
+        }
+    }
+}
+
+/// The chunks `Png::recover` could salvage and what was wrong with the rest.
+pub struct Recovery {
+    pub png: Png,
+    pub problems: Vec<Problem>,
+}
+
+/// Reads as many chunks as possible from `bytes`, see `Png::recover`.
+pub(crate) fn recover(bytes: &[u8], resync: bool) -> Recovery {
+    let mut chunks = Vec::new();
+    let mut problems = Vec::new();
+
+    if bytes.len() < 8 || bytes[..8] != Png::STANDARD_HEADER {
+        problems.push(Problem::Unreadable(PngError::BadSignature {
+            found: bytes[..bytes.len().min(8)].to_vec(),
+        }));
+    }
+
+    let mut budget = bytes.len().saturating_mul(RESYNC_CRC_BUDGET);
+    let mut pointer = 8;
+    while pointer < bytes.len() {
+        let index = chunks.len();
+        let error = match read_chunk(&bytes[pointer..]) {
+            Ok((chunk, length)) => {
+                chunks.push(chunk);
+                pointer += length;
+                continue
+            }
+            Err(Damage::BadCrc(chunk, length, e)) => {
+                problems.push(Problem::CrcFixed(e.at(pointer, index)));
+                chunks.push(chunk);
+                pointer += length;
+                continue
+            }
+            Err(Damage::Unreadable(e)) => e.at(pointer, index),
+        };
+        problems.push(Problem::Unreadable(error));
+
+        if !resync {
+            break
+        }
+        match next_chunk(bytes, pointer + 1, &mut budget) {
+            Some(next) => {
+                problems.push(Problem::Skipped { offset: pointer, length: next - pointer });
+                pointer = next;
+            }
+            None => {
+                problems.push(Problem::Skipped { offset: pointer, length: bytes.len() - pointer });
+                break
+            }
+        }
+    }
+
+    Recovery { png: Png::from_chunks(chunks), problems }
+}
+
+enum Damage {
+    /// Everything but the CRC checks out
+    BadCrc(Chunk, usize, PngError),
+    Unreadable(PngError),
+}
+
+/// Reads the chunk at the start of `bytes` and returns it with the number
+/// of bytes it took up. Errors are relative to the start of `bytes`.
+fn read_chunk(bytes: &[u8]) -> Result<(Chunk, usize), Damage> {
+    if bytes.len() < 12 {
+        return Err(Damage::Unreadable(PngError::TruncatedLength { offset: 0, chunk_index: 0 }))
+    }
+
+    let length = u32::from_be_bytes(bytes[0..4].try_into().unwrap()) as usize;
+    let end = (length + 12).min(bytes.len());
+
+    match Chunk::parse(&bytes[..end], true) {
+        Ok(chunk) => Ok((chunk, end)),
+        Err(e @ PngError::CrcMismatch { .. }) => {
+            let mut chunk = Chunk::parse(&bytes[..end], false).map_err(Damage::Unreadable)?;
+            chunk.recompute_crc();
+            Err(Damage::BadCrc(chunk, end, e))
+        }
+        Err(e) => Err(Damage::Unreadable(e)),
+    }
+}
+
+/// Resyncing computes CRCs over at most this many times the size of the
+/// file, however many candidates look like chunks.
+const RESYNC_CRC_BUDGET: usize = 4;
+
+/// Offset of the first chunk at or after `from` that reads without any
+/// problem, CRC included, so random bytes are not mistaken for a chunk.
+/// Only a candidate followed by another plausible header, or by the end of
+/// the file, has its CRC checked, and the bytes checked come out of
+/// `budget`. Gives up once the budget runs out.
+fn next_chunk(bytes: &[u8], from: usize, budget: &mut usize) -> Option<usize> {
+    for offset in from..bytes.len().saturating_sub(11) {
+        let candidate = &bytes[offset..];
+        let Some(length) = plausible_header(candidate) else { continue };
+        let end = length + 12;
+        let after = &candidate[end..];
+        if after.len() >= 8 && plausible_header(after).is_none() {
+            continue
+        }
+
+        if *budget < end {
+            return None
+        }
+        *budget -= end;
+        if Chunk::parse(&candidate[..end], true).is_ok() {
+            return Some(offset)
+        }
+    }
+    None
+}
+
+/// The data length of the chunk header at the start of `bytes`, if the
+/// type is all letters and the data fits in `bytes`.
+fn plausible_header(bytes: &[u8]) -> Option<usize> {
+    let length = u32::from_be_bytes(bytes[0..4].try_into().unwrap()) as usize;
+    let fits = length <= Chunk::MAX_LENGTH && length + 12 <= bytes.len();
+    (fits && bytes[4..8].iter().all(u8::is_ascii_alphabetic)).then_some(length)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::chunk_from_strings;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "one").unwrap(),
+            chunk_from_strings("IDAT", "two").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    /// Offset of the chunk at `index` in `testing_bytes`.
+    fn offset_of(index: usize) -> usize {
+        [8, 8 + 18, 8 + 18 + 15, 8 + 18 + 15 + 15][index]
+    }
+
+    #[test]
+    fn test_intact_file() {
+        let recovery = Png::recover(&testing_bytes(), true);
+        assert!(recovery.problems.is_empty());
+        assert_eq!(recovery.png.as_bytes(), testing_bytes());
+    }
+
+    #[test]
+    fn test_crc_mismatch_is_kept_and_fixed() {
+        let mut bytes = testing_bytes();
+        bytes[offset_of(1) + 8] ^= 0xff;
+
+        let recovery = Png::recover(&bytes, false);
+        assert_eq!(chunk_types(&recovery.png), ["IHDR", "IDAT", "IDAT", "IEND"]);
+        assert!(matches!(recovery.problems[..], [Problem::CrcFixed(PngError::CrcMismatch { chunk_index: 1, .. })]));
+        assert!(Png::try_from(recovery.png.as_bytes().as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_truncated_tail() {
+        let bytes = testing_bytes();
+        let recovery = Png::recover(&bytes[..bytes.len() - 5], false);
+
+        assert_eq!(chunk_types(&recovery.png), ["IHDR", "IDAT", "IDAT"]);
+        assert!(matches!(recovery.problems[..], [Problem::Unreadable(PngError::TruncatedLength { chunk_index: 3, .. })]));
+    }
+
+    #[test]
+    fn test_absurd_length_stops_without_resync() {
+        let mut bytes = testing_bytes();
+        bytes[offset_of(1)..offset_of(1) + 4].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff]);
+
+        let recovery = Png::recover(&bytes, false);
+        assert_eq!(chunk_types(&recovery.png), ["IHDR"]);
+        assert!(matches!(recovery.problems[..], [Problem::Unreadable(PngError::TruncatedData { chunk_index: 1, .. })]));
+    }
+
+    #[test]
+    fn test_resync_skips_to_next_chunk() {
+        let mut bytes = testing_bytes();
+        bytes[offset_of(1)..offset_of(1) + 4].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff]);
+
+        let recovery = Png::recover(&bytes, true);
+        assert_eq!(chunk_types(&recovery.png), ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(recovery.png.chunks()[1].data(), b"two");
+        assert_eq!(recovery.problems[1], Problem::Skipped { offset: offset_of(1), length: 15 });
+    }
+
+    #[test]
+    fn test_resync_through_garbage() {
+        let mut bytes = testing_bytes();
+        let garbage = [0xde, 0xad, 0xbe, 0xef, b'I', b'D', b'A', b'T', 0, 0];
+        bytes.splice(offset_of(2)..offset_of(2), garbage);
+
+        let recovery = Png::recover(&bytes, true);
+        assert_eq!(recovery.png.as_bytes(), testing_bytes());
+        assert_eq!(recovery.problems.len(), 2);
+    }
+
+    #[test]
+    fn test_resync_through_chunk_lookalikes() {
+        // an unreadable header, then every 8 bytes a letters-only type and
+        // a length that fits
+        let mut bytes = testing_bytes();
+        let mut garbage = vec![0xde, 0xad, 0xbe, 0xef, b'I', b'D', b'A', b'T'];
+        garbage.extend([0, 0, 0x40, 0, b'A', b'A', b'A', b'A'].repeat(1 << 15));
+        bytes.splice(offset_of(2)..offset_of(2), garbage);
+
+        let recovery = Png::recover(&bytes, true);
+        assert_eq!(recovery.png.as_bytes(), testing_bytes());
+    }
+
+    #[test]
+    fn test_bad_signature_still_reads_chunks() {
+        let mut bytes = testing_bytes();
+        bytes[1] = b'X';
+
+        let recovery = Png::recover(&bytes, false);
+        assert_eq!(recovery.png.chunks().len(), 4);
+        assert!(matches!(recovery.problems[..], [Problem::Unreadable(PngError::BadSignature { .. })]));
+        assert!(Png::recover(&[137, 80], true).png.chunks().is_empty());
+    }
+}
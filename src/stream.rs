@@ -0,0 +1,280 @@
+use crate::chunk::Chunk;
+use crate::png::{ChunkPosition, Png, PngError};
+use crate::Error;
+use std::io::{self, Read, Write};
+
+/// Reads a PNG one chunk at a time, so only the chunk being looked at is
+/// held in memory. Each chunk is checked for length and CRC like
+/// `Png::try_from` does.
+pub struct PngReader<R: Read> {
+    reader: R,
+    offset: usize,
+    index: usize,
+    done: bool,
+}
+
+impl<R: Read> PngReader<R> {
+    /// Reads and checks the signature.
+    pub fn new(mut reader: R) -> Result<PngReader<R>, Error> {
+        let mut header = Vec::with_capacity(8);
+        (&mut reader).take(8).read_to_end(&mut header)?;
+        if header != Png::STANDARD_HEADER {
+            return Err(PngError::BadSignature { found: header }.into())
+        }
+
+        Ok(PngReader { reader, offset: 8, index: 0, done: false })
+    }
+
+    /// Byte offset of the next chunk to be read.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<Chunk>, Error> {
+        let mut length_bytes = Vec::with_capacity(4);
+        (&mut self.reader).take(4).read_to_end(&mut length_bytes)?;
+        if length_bytes.is_empty() {
+            return Ok(None)
+        }
+
+        let mut header = length_bytes;
+        if header.len() == 4 {
+            (&mut self.reader).take(4).read_to_end(&mut header)?;
+        }
+        if header.len() < 8 {
+            return Err(PngError::TruncatedLength { offset: self.offset, chunk_index: self.index }.into())
+        }
+
+        let length = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let chunk_type: [u8; 4] = header[4..8].try_into().unwrap();
+
+        // read through `take` so a corrupt length cannot make us allocate
+        // more than the input actually holds
+        let mut bytes = header;
+        (&mut self.reader).take(length as u64 + 4).read_to_end(&mut bytes)?;
+        if bytes.len() < length + 12 {
+            return Err(PngError::TruncatedData {
+                offset: self.offset + 8,
+                chunk_index: self.index,
+                chunk_type,
+                expected: length,
+                available: (bytes.len() - 8).saturating_sub(4),
+            }
+            .into())
+        }
+
+        let chunk = Chunk::try_from(&bytes).map_err(|e| e.at(self.offset, self.index))?;
+        self.offset += bytes.len();
+        self.index += 1;
+
+        Ok(Some(chunk))
+    }
+}
+
+impl<R: Read> Iterator for PngReader<R> {
+    type Item = Result<Chunk, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+
+        let result = self.read_chunk().transpose();
+        // stop after the end of input or the first error
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+}
+
+/// Writes a PNG one chunk at a time.
+pub struct PngWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PngWriter<W> {
+    /// Writes the signature.
+    pub fn new(mut writer: W) -> io::Result<PngWriter<W>> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        Ok(PngWriter { writer })
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> io::Result<()> {
+        self.writer.write_all(&chunk.length().to_be_bytes())?;
+        self.writer.write_all(&chunk.chunk_type().bytes())?;
+        self.writer.write_all(chunk.data())?;
+        self.writer.write_all(&chunk.crc().to_be_bytes())
+    }
+
+    /// Flushes and hands back the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Copies every chunk from `reader` to `writer`, adding `inserted` at
+/// `position` on the way. Falls back the same way `Png::insert_chunks`
+/// does when the image lacks the chunk `position` refers to.
+pub fn copy_with_inserted<R: Read, W: Write>(
+    reader: PngReader<R>,
+    writer: &mut PngWriter<W>,
+    inserted: Vec<Chunk>,
+    position: ChunkPosition,
+) -> Result<(), Error> {
+    let mut pending = Some(inserted);
+    let mut previous: Option<[u8; 4]> = None;
+
+    for chunk in reader {
+        let chunk = chunk?;
+        let name = chunk.chunk_type().bytes();
+
+        let insert_here = match position {
+            ChunkPosition::AfterIhdr => previous == Some(*b"IHDR") || (previous.is_none() && &name != b"IHDR"),
+            ChunkPosition::BeforeFirstIdat => &name == b"IDAT" || &name == b"IEND",
+            ChunkPosition::AfterLastIdat => {
+                (previous == Some(*b"IDAT") && &name != b"IDAT") || &name == b"IEND"
+            }
+            ChunkPosition::BeforeIend => &name == b"IEND",
+        };
+        if insert_here {
+            for new_chunk in pending.take().unwrap_or_default() {
+                writer.write_chunk(&new_chunk)?;
+            }
+        }
+
+        writer.write_chunk(&chunk)?;
+        previous = Some(name);
+    }
+
+    for new_chunk in pending.take().unwrap_or_default() {
+        writer.write_chunk(&new_chunk)?;
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::chunk_from_strings;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "one").unwrap(),
+            chunk_from_strings("IDAT", "two").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    fn chunk_names(bytes: &[u8]) -> Vec<String> {
+        PngReader::new(bytes)
+            .unwrap()
+            .map(|chunk| chunk.unwrap().chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_reader_matches_png() {
+        let bytes = testing_bytes();
+        let streamed: Vec<Vec<u8>> = PngReader::new(bytes.as_slice())
+            .unwrap()
+            .map(|chunk| chunk.unwrap().as_bytes())
+            .collect();
+        let parsed: Vec<Vec<u8>> = Png::try_from(bytes.as_slice())
+            .unwrap()
+            .chunks()
+            .iter()
+            .map(Chunk::as_bytes)
+            .collect();
+
+        assert_eq!(streamed, parsed);
+    }
+
+    #[test]
+    fn test_reader_offset() {
+        let bytes = testing_bytes();
+        let mut reader = PngReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.offset(), 8);
+        reader.next();
+        assert_eq!(reader.offset(), 8 + 12 + "header".len());
+    }
+
+    #[test]
+    fn test_reader_bad_signature() {
+        assert!(PngReader::new(&b"GIF89a"[..]).is_err());
+    }
+
+    #[test]
+    fn test_reader_stops_at_first_error() {
+        let mut bytes = testing_bytes();
+        let second_data = 8 + 12 + "header".len() + 8;
+        bytes[second_data] ^= 0xff;
+
+        let mut reader = PngReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        let error = reader.next().unwrap().unwrap_err();
+        assert!(error.to_string().contains("chunk #1 (IDAT)"));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_reader_truncated() {
+        let bytes = testing_bytes();
+        for cut in [3, 9, 20] {
+            let truncated = &bytes[..bytes.len() - cut];
+            let results: Vec<_> = PngReader::new(truncated).unwrap().collect();
+            assert!(results.last().unwrap().is_err(), "cut {}", cut);
+        }
+    }
+
+    #[test]
+    fn test_reader_absurd_length() {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend_from_slice(&[0x7f, 0xff, 0xff, 0xff]);
+        bytes.extend_from_slice(b"IDAT");
+        bytes.extend_from_slice(&[0; 10]);
+
+        let results: Vec<_> = PngReader::new(bytes.as_slice()).unwrap().collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    }
+
+    #[test]
+    fn test_writer_round_trip() {
+        let bytes = testing_bytes();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        for chunk in PngReader::new(bytes.as_slice()).unwrap() {
+            writer.write_chunk(&chunk.unwrap()).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_copy_with_inserted_positions() {
+        let cases = [
+            (ChunkPosition::AfterIhdr, ["IHDR", "TeSt", "IDAT", "IDAT", "IEND"]),
+            (ChunkPosition::BeforeFirstIdat, ["IHDR", "TeSt", "IDAT", "IDAT", "IEND"]),
+            (ChunkPosition::AfterLastIdat, ["IHDR", "IDAT", "IDAT", "TeSt", "IEND"]),
+            (ChunkPosition::BeforeIend, ["IHDR", "IDAT", "IDAT", "TeSt", "IEND"]),
+        ];
+
+        for (position, expected) in cases {
+            let bytes = testing_bytes();
+            let mut writer = PngWriter::new(Vec::new()).unwrap();
+            let inserted = vec![chunk_from_strings("TeSt", "message").unwrap()];
+            copy_with_inserted(PngReader::new(bytes.as_slice()).unwrap(), &mut writer, inserted, position).unwrap();
+
+            let written = writer.finish().unwrap();
+            assert_eq!(chunk_names(&written), expected, "{:?}", position);
+
+            let mut png = Png::try_from(bytes.as_slice()).unwrap();
+            png.insert_chunk(chunk_from_strings("TeSt", "message").unwrap(), position);
+            assert_eq!(png.as_bytes(), written, "{:?}", position);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Error;
+use std::io;
+use std::str::FromStr;
+
+/// Chunks that can tell who made an image, where or when.
+pub const IDENTIFYING: [&str; 5] = ["eXIf", "tEXt", "iTXt", "zTXt", "tIME"];
+
+/// Chunks that change how the colors are displayed. No preset removes them.
+pub const COLOR: [&str; 4] = ["gAMA", "sRGB", "iCCP", "cHRM"];
+
+/// The chunks a `StripPolicy` removes before `keep` and `drop` apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preset {
+    /// `IDENTIFYING` chunks and private chunks
+    #[default]
+    Safe,
+    /// Every ancillary chunk but the `COLOR` ones
+    AllAncillary,
+}
+
+/// Which ancillary chunks `Png::strip` removes. Critical chunks are never
+/// removed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StripPolicy {
+    pub preset: Preset,
+    /// Chunk types that stay whatever the preset says
+    pub keep: Vec<ChunkType>,
+    /// Chunk types removed on top of the preset
+    pub drop: Vec<ChunkType>,
+}
+
+impl StripPolicy {
+    /// A policy from chunk type names, refusing to drop critical chunks.
+    pub fn new(preset: Preset, keep: &[String], drop: &[String]) -> Result<StripPolicy, Error> {
+        let parse = |names: &[String]| names.iter().map(|name| ChunkType::from_str(name)).collect::<Result<Vec<_>, _>>();
+        let (keep, drop) = (parse(keep)?, parse(drop)?);
+
+        if let Some(critical) = drop.iter().find(|chunk_type| chunk_type.is_critical()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is a critical chunk, the image is unreadable without it", critical),
+            )
+            .into())
+        }
+        Ok(StripPolicy { preset, keep, drop })
+    }
+
+    /// Whether chunks of `chunk_type` are removed.
+    pub fn removes(&self, chunk_type: &ChunkType) -> bool {
+        if chunk_type.is_critical() || self.keep.contains(chunk_type) {
+            return false
+        }
+        if self.drop.contains(chunk_type) {
+            return true
+        }
+
+        let name = chunk_type.to_string();
+        match self.preset {
+            Preset::Safe => IDENTIFYING.contains(&name.as_str()) || !chunk_type.is_public(),
+            Preset::AllAncillary => !COLOR.contains(&name.as_str()),
+        }
+    }
+}
+
+/// Removes the chunks `policy` picks from `png` and returns them in file
+/// order, see `Png::strip`.
+pub(crate) fn strip(png: &mut Png, policy: &StripPolicy) -> Vec<Chunk> {
+    png.remove_chunks_where(|chunk| policy.removes(chunk.chunk_type()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::chunk_from_strings;
+
+    fn testing_png() -> Png {
+        let chunks = ["IHDR", "gAMA", "iCCP", "tIME", "pHYs", "prVt", "IDAT", "tEXt", "eXIf", "IEND", "rust"]
+            .iter()
+            .map(|name| chunk_from_strings(name, "data").unwrap())
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    fn chunk_types(chunks: &[Chunk]) -> Vec<String> {
+        chunks.iter().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_safe_preset() {
+        let mut png = testing_png();
+        let removed = png.strip(&StripPolicy::default());
+
+        assert_eq!(chunk_types(&removed), ["tIME", "prVt", "tEXt", "eXIf", "rust"]);
+        assert_eq!(chunk_types(png.chunks()), ["IHDR", "gAMA", "iCCP", "pHYs", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_all_ancillary_preset() {
+        let mut png = testing_png();
+        let policy = StripPolicy { preset: Preset::AllAncillary, ..StripPolicy::default() };
+        png.strip(&policy);
+
+        assert_eq!(chunk_types(png.chunks()), ["IHDR", "gAMA", "iCCP", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_keep_and_drop() {
+        let mut png = testing_png();
+        let policy = StripPolicy::new(Preset::Safe, &names(&["tEXt", "pHYs"]), &names(&["pHYs", "iCCP"])).unwrap();
+        png.strip(&policy);
+
+        // keep wins over drop
+        assert_eq!(chunk_types(png.chunks()), ["IHDR", "gAMA", "pHYs", "IDAT", "tEXt", "IEND"]);
+    }
+
+    #[test]
+    fn test_critical_chunks_stay() {
+        assert!(StripPolicy::new(Preset::Safe, &[], &names(&["IDAT"])).is_err());
+        assert!(StripPolicy::new(Preset::Safe, &[], &names(&["no"])).is_err());
+
+        let policy = StripPolicy { preset: Preset::AllAncillary, ..StripPolicy::default() };
+        assert!(!policy.removes(&ChunkType::from_str("PLTE").unwrap()));
+        assert!(!policy.removes(&ChunkType::from_str("RuSt").unwrap()));
+    }
+}
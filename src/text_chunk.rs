@@ -0,0 +1,289 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::zlib;
+use crate::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// Which of the three textual chunk types a `TextChunk` is stored as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextKind {
+    /// tEXt: uncompressed Latin-1
+    Text,
+    /// zTXt: zlib compressed Latin-1
+    Compressed,
+    /// iTXt: UTF-8, optionally compressed, with a language tag and a
+    /// keyword translated into that language
+    International {
+        compressed: bool,
+        language_tag: String,
+        translated_keyword: String,
+    },
+}
+
+/// A decoded tEXt, zTXt or iTXt chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub keyword: String,
+    pub text: String,
+    pub kind: TextKind,
+}
+
+impl TextChunk {
+    pub fn new(keyword: &str, text: &str, kind: TextKind) -> Result<TextChunk, Error> {
+        validate_keyword(keyword)?;
+        let text_chunk = TextChunk { keyword: keyword.to_string(), text: text.to_string(), kind };
+        // encoding checks Latin-1, language tag and null separators
+        text_chunk.to_chunk()?;
+        Ok(text_chunk)
+    }
+
+    /// Whether `chunk_type` is one of tEXt, zTXt or iTXt.
+    pub fn is_text_type(chunk_type: &ChunkType) -> bool {
+        matches!(&chunk_type.bytes(), b"tEXt" | b"zTXt" | b"iTXt")
+    }
+
+    pub fn chunk_type(&self) -> ChunkType {
+        let name = match self.kind {
+            TextKind::Text => "tEXt",
+            TextKind::Compressed => "zTXt",
+            TextKind::International { .. } => "iTXt",
+        };
+        ChunkType::from_str(name).unwrap()
+    }
+
+    /// Encodes this text back into a chunk.
+    pub fn to_chunk(&self) -> Result<Chunk, Error> {
+        let mut data = to_latin1(&self.keyword)?;
+        data.push(0);
+
+        match &self.kind {
+            TextKind::Text => {
+                data.extend(to_latin1(&self.text)?);
+            }
+            TextKind::Compressed => {
+                data.push(0);
+                data.extend(zlib::compress(&to_latin1(&self.text)?, 9));
+            }
+            TextKind::International { compressed, language_tag, translated_keyword } => {
+                validate_language_tag(language_tag)?;
+                if translated_keyword.contains('\0') {
+                    return Err(invalid("translated keyword must not contain a null byte"))
+                }
+
+                data.extend([u8::from(*compressed), 0]);
+                data.extend(language_tag.as_bytes());
+                data.push(0);
+                data.extend(translated_keyword.as_bytes());
+                data.push(0);
+                if *compressed {
+                    data.extend(zlib::compress(self.text.as_bytes(), 9));
+                } else {
+                    data.extend(self.text.as_bytes());
+                }
+            }
+        }
+
+        Chunk::try_new(self.chunk_type(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for TextChunk {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+        let (keyword, rest) = split_at_null(data)
+            .ok_or_else(|| invalid("text chunk has no keyword separator"))?;
+        let keyword = from_latin1(keyword);
+        validate_keyword(&keyword)?;
+
+        let (text, kind) = match &chunk.chunk_type().bytes() {
+            b"tEXt" => (from_latin1(rest), TextKind::Text),
+            b"zTXt" => {
+                let (&method, compressed) = rest
+                    .split_first()
+                    .ok_or_else(|| invalid("zTXt chunk has no compression method"))?;
+                check_compression_method(method)?;
+                (from_latin1(&zlib::decompress(compressed)?), TextKind::Compressed)
+            }
+            b"iTXt" => {
+                if rest.len() < 2 {
+                    return Err(invalid("iTXt chunk has no compression flag"))
+                }
+                let compressed = match rest[0] {
+                    0 => false,
+                    1 => true,
+                    flag => return Err(invalid(format!("iTXt compression flag {} is not 0 or 1", flag))),
+                };
+                check_compression_method(rest[1])?;
+
+                let (language_tag, rest) = split_at_null(&rest[2..])
+                    .ok_or_else(|| invalid("iTXt chunk has no language tag separator"))?;
+                let (translated_keyword, text) = split_at_null(rest)
+                    .ok_or_else(|| invalid("iTXt chunk has no translated keyword separator"))?;
+
+                let text = if compressed { zlib::decompress(text)? } else { text.to_vec() };
+                let kind = TextKind::International {
+                    compressed,
+                    language_tag: String::from_utf8(language_tag.to_vec())?,
+                    translated_keyword: String::from_utf8(translated_keyword.to_vec())?,
+                };
+                (String::from_utf8(text)?, kind)
+            }
+            _ => return Err(invalid(format!("{} is not a text chunk", chunk.chunk_type()))),
+        };
+
+        Ok(TextChunk { keyword, text, kind })
+    }
+}
+
+impl fmt::Display for TextChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.chunk_type(), self.keyword)?;
+        if let TextKind::International { language_tag, translated_keyword, .. } = &self.kind {
+            if !language_tag.is_empty() || !translated_keyword.is_empty() {
+                write!(f, " [{}] {}", language_tag, translated_keyword)?;
+            }
+        }
+        write!(f, ": {}", self.text)
+    }
+}
+
+/// Keywords are 1-79 printable Latin-1 characters without leading,
+/// trailing or consecutive spaces.
+pub fn validate_keyword(keyword: &str) -> Result<(), Error> {
+    let count = keyword.chars().count();
+    if count == 0 || count > 79 {
+        return Err(invalid(format!("keyword must be 1-79 characters, found {}", count)))
+    }
+
+    let printable = |c: char| (' '..='~').contains(&c) || ('\u{a1}'..='\u{ff}').contains(&c);
+    if let Some(c) = keyword.chars().find(|c| !printable(*c)) {
+        return Err(invalid(format!("keyword contains non printable Latin-1 character {:?}", c)))
+    }
+
+    if keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ") {
+        return Err(invalid("keyword must not have leading, trailing or consecutive spaces"))
+    }
+
+    Ok(())
+}
+
+/// Language tags per RFC 3066: alphanumeric words separated by hyphens.
+fn validate_language_tag(tag: &str) -> Result<(), Error> {
+    if tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        Ok(())
+    } else {
+        Err(invalid(format!("language tag {:?} is not valid", tag)))
+    }
+}
+
+fn check_compression_method(method: u8) -> Result<(), Error> {
+    if method != 0 {
+        return Err(invalid(format!("unknown compression method {}", method)))
+    }
+    Ok(())
+}
+
+fn split_at_null(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let position = bytes.iter().position(|&b| b == 0)?;
+    Some((&bytes[..position], &bytes[position + 1..]))
+}
+
+/// Latin-1 maps each byte straight to the code point of the same value.
+fn from_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+fn to_latin1(text: &str) -> Result<Vec<u8>, Error> {
+    text.chars()
+        .map(|c| match u8::try_from(c) {
+            Ok(0) => Err(invalid("text must not contain a null byte")),
+            Ok(b) => Ok(b),
+            Err(_) => Err(invalid(format!("{:?} cannot be stored as Latin-1, use iTXt", c))),
+        })
+        .collect()
+}
+
+fn invalid<M: Into<String>>(message: M) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into()).into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn international(compressed: bool) -> TextKind {
+        TextKind::International {
+            compressed,
+            language_tag: "de-CH".to_string(),
+            translated_keyword: "Titel".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_each_kind() {
+        let kinds = [TextKind::Text, TextKind::Compressed, international(false), international(true)];
+        for kind in kinds {
+            let text = if kind == TextKind::Text || kind == TextKind::Compressed { "caf\u{e9}" } else { "Grüße ✓" };
+            let original = TextChunk::new("Title", text, kind).unwrap();
+            let chunk = original.to_chunk().unwrap();
+            let decoded = TextChunk::try_from(&chunk).unwrap();
+            assert_eq!(decoded, original);
+        }
+    }
+
+    #[test]
+    fn test_text_is_latin1() {
+        let chunk = Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Author\0Jos\xe9".to_vec());
+        let text = TextChunk::try_from(&chunk).unwrap();
+        assert_eq!(text.keyword, "Author");
+        assert_eq!(text.text, "Jos\u{e9}");
+        assert_eq!(text.to_chunk().unwrap().data(), chunk.data());
+    }
+
+    #[test]
+    fn test_non_latin1_text_needs_itxt() {
+        assert!(TextChunk::new("Title", "✓", TextKind::Text).is_err());
+        assert!(TextChunk::new("Title", "✓", TextKind::Compressed).is_err());
+        assert!(TextChunk::new("Title", "✓", international(false)).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_keywords() {
+        let long = "k".repeat(80);
+        for keyword in ["", " Title", "Title ", "Ti  tle", "Ti\ntle", "T\u{2713}", long.as_str()] {
+            assert!(validate_keyword(keyword).is_err(), "{:?}", keyword);
+        }
+        assert!(validate_keyword("Creation Time").is_ok());
+        assert!(validate_keyword(&"k".repeat(79)).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_chunks() {
+        let chunk = Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"no separator".to_vec());
+        assert!(TextChunk::try_from(&chunk).is_err());
+
+        let chunk = Chunk::new(ChunkType::from_str("zTXt").unwrap(), b"Title\0\x00not zlib".to_vec());
+        assert!(TextChunk::try_from(&chunk).is_err());
+
+        let chunk = Chunk::new(ChunkType::from_str("iTXt").unwrap(), b"Title\0\x02\x00\0\0text".to_vec());
+        assert!(TextChunk::try_from(&chunk).is_err());
+
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"Title\0text".to_vec());
+        assert!(TextChunk::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_bad_language_tag() {
+        let kind = TextKind::International {
+            compressed: false,
+            language_tag: "en us".to_string(),
+            translated_keyword: String::new(),
+        };
+        assert!(TextChunk::new("Title", "text", kind).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use crate::chunk::Chunk;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+use std::fmt;
+
+/// Ancillary chunks the spec allows at most once per image.
+const SINGLETONS: [&[u8; 4]; 9] = [b"tIME", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT", b"bKGD", b"tRNS", b"pHYs"];
+/// Ancillary chunks that must come before PLTE as well as before IDAT.
+const BEFORE_PLTE: [&[u8; 4]; 5] = [b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT"];
+/// Ancillary chunks that must come after PLTE but before IDAT.
+const AFTER_PLTE: [&[u8; 4]; 3] = [b"bKGD", b"tRNS", b"hIST"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Allowed by the spec, but likely to confuse decoders
+    Warning,
+    /// Breaks a rule the spec states with "shall"
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One broken rule found by `Png::validate`.
+///
+/// `chunk_index` is the zero based position of the offending chunk, or
+/// `None` when the problem is something missing from the whole file.
+/// `spec` names the section of the PNG specification (third edition)
+/// that states the rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub chunk_index: Option<usize>,
+    pub message: String,
+    pub spec: &'static str,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.severity)?;
+        if let Some(index) = self.chunk_index {
+            write!(f, "chunk #{}: ", index)?;
+        }
+        write!(f, "{} (PNG {})", self.message, self.spec)
+    }
+}
+
+/// Checks the chunk layout rules of the spec. Chunks are assumed to be
+/// well formed already, which parsing guarantees.
+pub(crate) fn validate(png: &Png) -> Vec<Diagnostic> {
+    let mut checker = Checker { diagnostics: Vec::new() };
+    let chunks = png.chunks();
+    let name = |index: usize| chunks[index].chunk_type().bytes();
+    let first = |wanted: &[u8; 4]| chunks.iter().position(|c| &c.chunk_type().bytes() == wanted);
+
+    let ihdr = match chunks.first() {
+        Some(chunk) if &chunk.chunk_type().bytes() == b"IHDR" => match Ihdr::try_from(chunk) {
+            Ok(ihdr) => Some(ihdr),
+            Err(e) => {
+                checker.error(Some(0), format!("IHDR is invalid: {}", e), "11.2.2");
+                None
+            }
+        },
+        Some(_) => {
+            checker.error(Some(0), "the first chunk must be IHDR", "5.6");
+            None
+        }
+        None => {
+            checker.error(None, "the file has no chunks", "5.6");
+            return checker.diagnostics
+        }
+    };
+
+    let first_idat = first(b"IDAT");
+    let first_plte = first(b"PLTE");
+    let iend = first(b"IEND");
+    let mut seen: Vec<[u8; 4]> = Vec::new();
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        let bytes = chunk_type.bytes();
+
+        if !chunk_type.is_reserved_bit_valid() {
+            checker.error(Some(index), format!("{} has the reserved bit set", chunk_type), "5.4");
+        }
+        if chunk_type.is_critical() && !matches!(&bytes, b"IHDR" | b"PLTE" | b"IDAT" | b"IEND") {
+            checker.warning(Some(index), format!("{} is an unknown critical chunk", chunk_type), "5.4");
+        }
+
+        let repeated = seen.contains(&bytes);
+        match &bytes {
+            b"IHDR" if index > 0 => checker.error(Some(index), "IHDR must appear only once, first", "5.6"),
+            b"PLTE" if repeated => checker.error(Some(index), "PLTE must appear at most once", "5.6"),
+            b"IEND" if repeated => checker.error(Some(index), "IEND must appear only once", "5.6"),
+            b"IDAT" if repeated && name(index - 1) != *b"IDAT" => {
+                checker.error(Some(index), "IDAT chunks must be consecutive", "5.6")
+            }
+            _ if repeated && SINGLETONS.contains(&&bytes) => {
+                checker.error(Some(index), format!("{} must appear at most once", chunk_type), "5.6")
+            }
+            _ => {}
+        }
+        seen.push(bytes);
+
+        let after_idat = first_idat.is_some_and(|i| index > i);
+        let after_plte = first_plte.is_some_and(|i| index > i);
+        if &bytes == b"PLTE" && after_idat {
+            checker.error(Some(index), "PLTE must come before the first IDAT", "5.6");
+        } else if BEFORE_PLTE.contains(&&bytes) && (after_idat || after_plte) {
+            checker.error(Some(index), format!("{} must come before PLTE and IDAT", chunk_type), "5.6");
+        } else if AFTER_PLTE.contains(&&bytes) && (after_idat || (first_plte.is_some() && !after_plte)) {
+            checker.error(Some(index), format!("{} must come after PLTE and before IDAT", chunk_type), "5.6");
+        }
+
+        if &bytes == b"PLTE" {
+            check_palette(&mut checker, index, chunk, ihdr.as_ref());
+        }
+        if &bytes == b"IEND" && !chunk.data().is_empty() {
+            checker.error(Some(index), "IEND must be empty", "11.2.5");
+        }
+    }
+
+    if let Some(ihdr) = &ihdr {
+        match (&ihdr.color_type, first_plte) {
+            (ColorType::Indexed, None) => checker.error(None, "indexed color images need a PLTE chunk", "11.2.3"),
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, Some(index)) => {
+                checker.error(Some(index), "grayscale images must not have a PLTE chunk", "11.2.3")
+            }
+            _ => {}
+        }
+    }
+    if first_idat.is_none() {
+        checker.error(None, "there is no IDAT chunk", "11.2.4");
+    }
+    match iend {
+        None => checker.error(None, "there is no IEND chunk", "11.2.5"),
+        Some(index) if index + 1 < chunks.len() => {
+            checker.error(Some(index + 1), "IEND must be the last chunk", "5.6")
+        }
+        Some(_) => {}
+    }
+
+    checker.diagnostics.sort_by_key(|d| d.chunk_index.unwrap_or(usize::MAX));
+    checker.diagnostics
+}
+
+fn check_palette(checker: &mut Checker, index: usize, chunk: &Chunk, ihdr: Option<&Ihdr>) {
+    let length = chunk.data().len();
+    if length == 0 || !length.is_multiple_of(3) {
+        checker.error(Some(index), format!("PLTE is {} bytes, not a multiple of 3", length), "11.2.3");
+        return
+    }
+
+    let entries = length / 3;
+    let limit = match ihdr {
+        Some(ihdr) if ihdr.color_type == ColorType::Indexed => 1 << ihdr.bit_depth,
+        _ => 256,
+    };
+    if entries > limit {
+        checker.error(Some(index), format!("PLTE has {} entries, at most {} fit", entries, limit), "11.2.3");
+    }
+}
+
+struct Checker {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn error<M: Into<String>>(&mut self, chunk_index: Option<usize>, message: M, spec: &'static str) {
+        self.push(Severity::Error, chunk_index, message, spec);
+    }
+
+    fn warning<M: Into<String>>(&mut self, chunk_index: Option<usize>, message: M, spec: &'static str) {
+        self.push(Severity::Warning, chunk_index, message, spec);
+    }
+
+    fn push<M: Into<String>>(&mut self, severity: Severity, chunk_index: Option<usize>, message: M, spec: &'static str) {
+        self.diagnostics.push(Diagnostic { severity, chunk_index, message: message.into(), spec });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::chunk_from_strings;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    /// 1x1 image of `color_type` at 8 bits per sample.
+    fn ihdr(color_type: u8) -> Chunk {
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 1, 8, color_type];
+        data.extend_from_slice(&[0, 0, 0]);
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+
+    fn png(names: &[&str]) -> Png {
+        let chunks = names
+            .iter()
+            .map(|name| match *name {
+                "IHDR" => ihdr(6),
+                "IEND" => chunk_from_strings("IEND", "").unwrap(),
+                "PLTE" => chunk_from_strings("PLTE", "rgb").unwrap(),
+                name => chunk_from_strings(name, "data").unwrap(),
+            })
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    fn problems(png: &Png) -> Vec<(Option<usize>, String)> {
+        png.validate().into_iter().map(|d| (d.chunk_index, d.message)).collect()
+    }
+
+    #[test]
+    fn test_valid_png() {
+        assert!(png(&["IHDR", "gAMA", "PLTE", "bKGD", "IDAT", "IDAT", "tEXt", "IEND"]).validate().is_empty());
+    }
+
+    #[test]
+    fn test_first_and_last_chunk() {
+        let problems = problems(&png(&["gAMA", "IHDR", "IDAT", "IEND", "ruSt"]));
+        assert_eq!(
+            problems,
+            vec![
+                (Some(0), "the first chunk must be IHDR".to_string()),
+                (Some(1), "IHDR must appear only once, first".to_string()),
+                (Some(4), "IEND must be the last chunk".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_chunks() {
+        let diagnostics = png(&["IHDR"]).validate();
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|d| d.chunk_index.is_none() && d.severity == Severity::Error));
+        assert_eq!(Png::from_chunks(vec![]).validate().len(), 1);
+    }
+
+    #[test]
+    fn test_idat_must_be_consecutive() {
+        let problems = problems(&png(&["IHDR", "IDAT", "tEXt", "IDAT", "IEND"]));
+        assert_eq!(problems, vec![(Some(3), "IDAT chunks must be consecutive".to_string())]);
+    }
+
+    #[test]
+    fn test_ordering_around_plte_and_idat() {
+        let problems = problems(&png(&["IHDR", "bKGD", "PLTE", "gAMA", "IDAT", "PLTE", "IEND"]));
+        assert_eq!(
+            problems,
+            vec![
+                (Some(1), "bKGD must come after PLTE and before IDAT".to_string()),
+                (Some(3), "gAMA must come before PLTE and IDAT".to_string()),
+                (Some(5), "PLTE must appear at most once".to_string()),
+                (Some(5), "PLTE must come before the first IDAT".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_singletons() {
+        let problems = problems(&png(&["IHDR", "sRGB", "sRGB", "IDAT", "tIME", "tIME", "tEXt", "tEXt", "IEND"]));
+        assert_eq!(
+            problems,
+            vec![
+                (Some(2), "sRGB must appear at most once".to_string()),
+                (Some(5), "tIME must appear at most once".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_palette_rules() {
+        let mut chunks = vec![ihdr(3), chunk_from_strings("IDAT", "data").unwrap(), chunk_from_strings("IEND", "").unwrap()];
+        assert_eq!(
+            problems(&Png::from_chunks(chunks.clone())),
+            vec![(None, "indexed color images need a PLTE chunk".to_string())]
+        );
+
+        chunks.insert(1, chunk_from_strings("PLTE", "four").unwrap());
+        assert_eq!(
+            problems(&Png::from_chunks(chunks.clone())),
+            vec![(Some(1), "PLTE is 4 bytes, not a multiple of 3".to_string())]
+        );
+
+        chunks[0] = ihdr(0);
+        chunks[1] = chunk_from_strings("PLTE", "rgb").unwrap();
+        assert_eq!(
+            problems(&Png::from_chunks(chunks)),
+            vec![(Some(1), "grayscale images must not have a PLTE chunk".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_unknown_critical_chunk_is_a_warning() {
+        let diagnostics = png(&["IHDR", "RUST", "IDAT", "IEND"]).validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].to_string(), "warning: chunk #1: RUST is an unknown critical chunk (PNG 5.4)");
+    }
+
+    #[test]
+    fn test_image_files() {
+        let dice = Png::try_from(std::fs::read("dice-png-41766.png").unwrap().as_slice()).unwrap();
+        let problems = problems(&dice);
+        assert_eq!(
+            problems,
+            vec![
+                (Some(10), "rust has the reserved bit set".to_string()),
+                (Some(10), "IEND must be the last chunk".to_string()),
+            ]
+        );
+    }
+}
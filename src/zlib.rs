@@ -0,0 +1,65 @@
+use crate::Error;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// Deflates `data` into a zlib stream (compression method 0 in PNG terms).
+/// `level` goes from 0 (store) to 9 (best).
+pub fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level.min(9)));
+    // writing into a Vec cannot fail
+    encoder.write_all(data).expect("write to Vec");
+    encoder.finish().expect("write to Vec")
+}
+
+/// Most bytes `decompress` inflates a stream to, so a small crafted
+/// stream cannot use up memory.
+pub const MAX_OUTPUT: usize = 256 << 20;
+
+/// Inflates a zlib stream, failing on corrupt or truncated input and on
+/// output over `MAX_OUTPUT`.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    decompress_limited(data, MAX_OUTPUT)
+}
+
+/// `decompress` with output over `limit` bytes refused instead.
+pub fn decompress_limited(data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(data).take(limit as u64 + 1).read_to_end(&mut decoded)?;
+    if decoded.len() > limit {
+        return Err(format!("zlib stream inflates to more than {} bytes", limit).into())
+    }
+    Ok(decoded)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = "a message that repeats, a message that repeats".repeat(10);
+        for level in [0, 6, 9] {
+            let compressed = compress(data.as_bytes(), level);
+            assert_eq!(decompress(&compressed).unwrap(), data.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_corrupt_stream() {
+        let mut compressed = compress(b"some data to compress", 6);
+        compressed.truncate(compressed.len() - 6);
+        assert!(decompress(&compressed).is_err());
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let compressed = compress(&vec![0; 1 << 20], 9);
+        assert!(compressed.len() < 2048);
+
+        assert!(decompress_limited(&compressed, (1 << 20) - 1).is_err());
+        assert_eq!(decompress_limited(&compressed, 1 << 20).unwrap().len(), 1 << 20);
+    }
+}